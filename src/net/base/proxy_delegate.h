@@ -15,6 +15,7 @@ class GURL;
 
 namespace net {
 
+class HostPortPair;
 class HttpRequestHeaders;
 class HttpResponseHeaders;
 class ProxyInfo;
@@ -44,9 +45,10 @@ class NET_EXPORT ProxyDelegate {
   // explicitly directed to skip a proxy).
   virtual void OnFallback(const ProxyServer& bad_proxy, int net_error) = 0;
 
-  // Called immediately before a proxy tunnel request is sent. Provides the
-  // embedder an opportunity to add extra request headers.
+  // Called immediately before a proxy tunnel request to |endpoint| is sent.
+  // Provides the embedder an opportunity to add extra request headers.
   virtual void OnBeforeTunnelRequest(const ProxyServer& proxy_server,
+                                     const HostPortPair& endpoint,
                                      HttpRequestHeaders* extra_headers) = 0;
 
   // Called when the response headers for the proxy tunnel request have been
@@ -53,6 +53,7 @@ void TestProxyDelegate::OnFallback(const ProxyServer& bad_proxy,
 
 void TestProxyDelegate::OnBeforeTunnelRequest(
     const ProxyServer& proxy_server,
+    const HostPortPair& endpoint,
     HttpRequestHeaders* extra_headers) {
   on_before_tunnel_request_called_ = true;
   if (extra_headers)
@@ -42,6 +42,7 @@ class TestProxyDelegate : public ProxyDelegate {
                       ProxyInfo* result) override;
   void OnFallback(const ProxyServer& bad_proxy, int net_error) override;
   void OnBeforeTunnelRequest(const ProxyServer& proxy_server,
+                             const HostPortPair& endpoint,
                              HttpRequestHeaders* extra_headers) override;
   Error OnTunnelHeadersReceived(
       const ProxyServer& proxy_server,
@@ -383,7 +383,7 @@ int HttpProxyClientSocket::DoSendRequest() {
 
     if (proxy_delegate_) {
       HttpRequestHeaders proxy_delegate_headers;
-      proxy_delegate_->OnBeforeTunnelRequest(proxy_server_,
+      proxy_delegate_->OnBeforeTunnelRequest(proxy_server_, endpoint_,
                                              &proxy_delegate_headers);
       extra_headers.MergeFrom(proxy_delegate_headers);
     }
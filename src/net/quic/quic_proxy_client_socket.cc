@@ -366,7 +366,7 @@ int QuicProxyClientSocket::DoSendRequest() {
 
   if (proxy_delegate_) {
     HttpRequestHeaders proxy_delegate_headers;
-    proxy_delegate_->OnBeforeTunnelRequest(proxy_server_,
+    proxy_delegate_->OnBeforeTunnelRequest(proxy_server_, endpoint_,
                                            &proxy_delegate_headers);
     if (proxy_delegate_headers.HasHeader("fastopen")) {
       proxy_delegate_headers.RemoveHeader("fastopen");
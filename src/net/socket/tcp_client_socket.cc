@@ -102,6 +102,10 @@ bool TCPClientSocket::SetNoDelay(bool no_delay) {
   return socket_->SetNoDelay(no_delay);
 }
 
+int TCPClientSocket::SetDiffServCodePoint(DiffServCodePoint dscp) {
+  return socket_->SetDiffServCodePoint(dscp);
+}
+
 void TCPClientSocket::SetBeforeConnectCallback(
     const BeforeConnectCallback& before_connect_callback) {
   DCHECK_EQ(CONNECT_STATE_NONE, next_connect_state_);
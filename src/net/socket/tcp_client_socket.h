@@ -79,6 +79,7 @@ class NET_EXPORT TCPClientSocket : public TransportClientSocket,
   int Bind(const IPEndPoint& address) override;
   bool SetKeepAlive(bool enable, int delay) override;
   bool SetNoDelay(bool no_delay) override;
+  int SetDiffServCodePoint(DiffServCodePoint dscp) override;
 
   // StreamSocket implementation.
   void SetBeforeConnectCallback(
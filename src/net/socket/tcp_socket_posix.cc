@@ -5,6 +5,7 @@
 #include "net/socket/tcp_socket.h"
 
 #include <errno.h>
+#include <netinet/in.h>
 #include <netinet/tcp.h>
 #include <sys/socket.h>
 
@@ -421,6 +422,26 @@ bool TCPSocketPosix::SetNoDelay(bool no_delay) {
   return SetTCPNoDelay(socket_->socket_fd(), no_delay) == OK;
 }
 
+int TCPSocketPosix::SetDiffServCodePoint(DiffServCodePoint dscp) {
+  if (!socket_)
+    return ERR_SOCKET_NOT_CONNECTED;
+
+  if (dscp == DSCP_NO_CHANGE)
+    return OK;
+
+  int dscp_and_ecn = dscp << 2;
+  // The socket may be of either family, and a dual-stack IPv6 socket uses
+  // both options, so it is enough for one of them to succeed.
+  int rv = setsockopt(socket_->socket_fd(), IPPROTO_IP, IP_TOS, &dscp_and_ecn,
+                      sizeof(dscp_and_ecn));
+  int rv6 = setsockopt(socket_->socket_fd(), IPPROTO_IPV6, IPV6_TCLASS,
+                       &dscp_and_ecn, sizeof(dscp_and_ecn));
+  if (rv < 0 && rv6 < 0)
+    return MapSystemError(errno);
+
+  return OK;
+}
+
 void TCPSocketPosix::Close() {
   socket_.reset();
   tag_ = SocketTag();
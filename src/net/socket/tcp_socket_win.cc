@@ -33,6 +33,7 @@
 #include "net/socket/socket_net_log_params.h"
 #include "net/socket/socket_options.h"
 #include "net/socket/socket_tag.h"
+#include "net/socket/udp_socket_win.h"
 
 namespace net {
 
@@ -265,6 +266,7 @@ TCPSocketWin::TCPSocketWin(
       waiting_connect_(false),
       waiting_read_(false),
       waiting_write_(false),
+      dscp_flow_pending_(false),
       connect_os_error_(0),
       logging_multiple_connect_attempts_(false),
       net_log_(NetLogWithSource::Make(net_log, NetLogSourceType::SOCKET)) {
@@ -681,6 +683,40 @@ bool TCPSocketWin::SetNoDelay(bool no_delay) {
   return SetTCPNoDelay(socket_, no_delay) == OK;
 }
 
+int TCPSocketWin::SetDiffServCodePoint(DiffServCodePoint dscp) {
+  DCHECK(!peer_address_);
+  if (socket_ == INVALID_SOCKET)
+    return ERR_SOCKET_NOT_CONNECTED;
+
+  if (dscp == DSCP_NO_CHANGE)
+    return OK;
+
+  // Setting IP_TOS is ignored by Windows.
+  QwaveApi* api = QwaveApi::GetDefault();
+  if (!api->qwave_supported())
+    return ERR_NOT_IMPLEMENTED;
+
+  if (!dscp_manager_) {
+    dscp_manager_ = std::make_unique<DscpManager>(api, socket_);
+    // |dscp_manager_| is owned by this socket.
+    dscp_manager_->SetHandleCreatedCallback(base::BindRepeating(
+        &TCPSocketWin::OnDscpHandleCreated, base::Unretained(this)));
+  }
+  dscp_manager_->Set(dscp);
+  return OK;
+}
+
+void TCPSocketWin::AddPeerToDscpFlow() {
+  // The qos handle is created asynchronously, and may not be ready yet.
+  dscp_flow_pending_ =
+      dscp_manager_->PrepareForSend(*peer_address_) == ERR_INVALID_HANDLE;
+}
+
+void TCPSocketWin::OnDscpHandleCreated() {
+  if (dscp_flow_pending_)
+    AddPeerToDscpFlow();
+}
+
 void TCPSocketWin::Close() {
   DCHECK_CALLED_ON_VALID_THREAD(thread_checker_);
 
@@ -688,6 +724,10 @@ void TCPSocketWin::Close() {
     // Only log the close event if there's actually a socket to close.
     net_log_.AddEvent(NetLogEventType::SOCKET_CLOSED);
 
+    // The flow must be removed before the socket is closed.
+    dscp_manager_.reset();
+    dscp_flow_pending_ = false;
+
     // Note: don't use CancelIo to cancel pending IO because it doesn't work
     // when there is a Winsock layered service provider.
 
@@ -897,6 +937,8 @@ void TCPSocketWin::DoConnectComplete(int result) {
                                    "os_error", os_error);
   } else {
     net_log_.EndEvent(NetLogEventType::TCP_CONNECT_ATTEMPT);
+    if (dscp_manager_)
+      AddPeerToDscpFlow();
   }
 
   if (!logging_multiple_connect_attempts_)
@@ -19,6 +19,7 @@
 #include "net/base/completion_once_callback.h"
 #include "net/base/net_export.h"
 #include "net/log/net_log_with_source.h"
+#include "net/socket/diff_serv_code_point.h"
 #include "net/socket/socket_descriptor.h"
 #include "net/socket/socket_performance_watcher.h"
 #include "net/traffic_annotation/network_traffic_annotation.h"
@@ -26,6 +27,7 @@
 namespace net {
 
 class AddressList;
+class DscpManager;
 class IOBuffer;
 class IPEndPoint;
 class NetLog;
@@ -90,6 +92,10 @@ class NET_EXPORT TCPSocketWin : public base::win::ObjectWatcher::Delegate {
   int SetSendBufferSize(int32_t size);
   bool SetKeepAlive(bool enable, int delay);
   bool SetNoDelay(bool no_delay);
+  // Sets the DSCP bits of outgoing packets through QWAVE, which adds the
+  // peer to a qos flow once the socket is connected. Must be called before
+  // Connect(). Returns a net error code.
+  int SetDiffServCodePoint(DiffServCodePoint dscp);
 
   // Gets the estimated RTT. Returns false if the RTT is
   // unavailable. May also return false when estimated RTT is 0.
@@ -149,6 +155,9 @@ class NET_EXPORT TCPSocketWin : public base::win::ObjectWatcher::Delegate {
   int DoConnect();
   void DoConnectComplete(int result);
 
+  void AddPeerToDscpFlow();
+  void OnDscpHandleCreated();
+
   void LogConnectBegin(const AddressList& addresses);
   void LogConnectEnd(int net_error);
 
@@ -191,6 +200,10 @@ class NET_EXPORT TCPSocketWin : public base::win::ObjectWatcher::Delegate {
   CompletionOnceCallback write_callback_;
 
   std::unique_ptr<IPEndPoint> peer_address_;
+  // Non-null once a DSCP value is set.
+  std::unique_ptr<DscpManager> dscp_manager_;
+  // Whether the connected peer waits for the qos handle to join the flow.
+  bool dscp_flow_pending_;
   // The OS error that a connect attempt last completed with.
   int connect_os_error_;
 
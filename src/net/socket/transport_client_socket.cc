@@ -4,6 +4,8 @@
 
 #include "net/socket/transport_client_socket.h"
 
+#include "net/base/net_errors.h"
+
 namespace net {
 
 TransportClientSocket::TransportClientSocket() = default;
@@ -19,4 +21,9 @@ bool TransportClientSocket::SetKeepAlive(bool enable, int delay_secs) {
   return false;
 }
 
+int TransportClientSocket::SetDiffServCodePoint(DiffServCodePoint dscp) {
+  NOTIMPLEMENTED();
+  return ERR_NOT_IMPLEMENTED;
+}
+
 }  // namespace net
@@ -8,6 +8,7 @@
 #include "base/macros.h"
 #include "net/base/ip_endpoint.h"
 #include "net/base/net_export.h"
+#include "net/socket/diff_serv_code_point.h"
 #include "net/socket/stream_socket.h"
 
 namespace net {
@@ -47,6 +48,12 @@ class NET_EXPORT TransportClientSocket : public StreamSocket {
   // during BeforeConnect handlers.
   virtual bool SetKeepAlive(bool enable, int delay_secs);
 
+  // Sets the DSCP bits of outgoing packets. Returns a net error code.
+  //
+  // Has the same requirement on the underlying platform socket as
+  // SetNoDelay().
+  virtual int SetDiffServCodePoint(DiffServCodePoint dscp);
+
  private:
   DISALLOW_COPY_AND_ASSIGN(TransportClientSocket);
 };
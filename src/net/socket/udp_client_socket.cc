@@ -141,6 +141,10 @@ int UDPClientSocket::SetDoNotFragment() {
   return socket_.SetDoNotFragment();
 }
 
+int UDPClientSocket::SetDiffServCodePoint(DiffServCodePoint dscp) {
+  return socket_.SetDiffServCodePoint(dscp);
+}
+
 void UDPClientSocket::SetMsgConfirm(bool confirm) {
   socket_.SetMsgConfirm(confirm);
 }
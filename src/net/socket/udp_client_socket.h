@@ -75,6 +75,9 @@ class NET_EXPORT_PRIVATE UDPClientSocket : public DatagramClientSocket {
   int SetMulticastInterface(uint32_t interface_index) override;
   void SetIOSNetworkServiceType(int ios_network_service_type) override;
 
+  // Sets the DSCP of outgoing packets. Must be called after connecting.
+  int SetDiffServCodePoint(DiffServCodePoint dscp);
+
  private:
   UDPSocket socket_;
   NetworkChangeNotifier::NetworkHandle network_;
@@ -1305,6 +1305,10 @@ int DscpManager::PrepareForSend(const IPEndPoint& remote_address) {
   return OK;
 }
 
+void DscpManager::SetHandleCreatedCallback(base::RepeatingClosure callback) {
+  handle_created_callback_ = std::move(callback);
+}
+
 void DscpManager::RequestHandle() {
   if (handle_is_initializing_)
     return;
@@ -1350,6 +1354,8 @@ void DscpManager::OnHandleCreated(QwaveApi* api,
 
   dscp_manager->qos_handle_ = handle;
   dscp_manager->handle_is_initializing_ = false;
+  if (dscp_manager->handle_created_callback_)
+    dscp_manager->handle_created_callback_.Run();
 }
 
 }  // namespace net
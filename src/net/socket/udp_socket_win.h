@@ -11,6 +11,7 @@
 
 #include <memory>
 
+#include "base/callback.h"
 #include "base/gtest_prod_util.h"
 #include "base/macros.h"
 #include "base/memory/ref_counted.h"
@@ -128,6 +129,10 @@ class NET_EXPORT DscpManager {
   // already. Does nothing if no DSCP value has been Set.
   int PrepareForSend(const IPEndPoint& remote_address);
 
+  // Runs |callback| whenever a new qos handle is ready, for callers that add
+  // remote addresses once instead of before every send.
+  void SetHandleCreatedCallback(base::RepeatingClosure callback);
+
  private:
   void RequestHandle();
   static HANDLE DoCreateHandle(QwaveApi* api);
@@ -146,6 +151,7 @@ class NET_EXPORT DscpManager {
   bool handle_is_initializing_ = false;
   // 0 means no flow has been constructed.
   QOS_FLOWID flow_id_ = 0;
+  base::RepeatingClosure handle_created_callback_;
   base::WeakPtrFactory<DscpManager> weak_ptr_factory_{this};
 
   DISALLOW_COPY_AND_ASSIGN(DscpManager);
@@ -0,0 +1,208 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/admin_server.h"
+
+#include <utility>
+#include <vector>
+
+#include "base/bind.h"
+#include "base/location.h"
+#include "base/logging.h"
+#include "base/strings/strcat.h"
+#include "base/strings/string_number_conversions.h"
+#include "base/strings/string_split.h"
+#include "base/strings/string_util.h"
+#include "base/threading/thread_task_runner_handle.h"
+#include "net/base/io_buffer.h"
+#include "net/base/net_errors.h"
+#include "net/socket/server_socket.h"
+#include "net/socket/stream_socket.h"
+#include "net/traffic_annotation/network_traffic_annotation.h"
+
+namespace net {
+
+namespace {
+constexpr int kBufferSize = 1024;
+constexpr char kProfilePath[] = "/profile";
+constexpr char kProfilePathPrefix[] = "/profile/";
+constexpr size_t kProfilePathPrefixSize = sizeof(kProfilePathPrefix) - 1;
+
+constexpr NetworkTrafficAnnotationTag kTrafficAnnotation =
+    DefineNetworkTrafficAnnotation("naive_admin", "");
+}  // namespace
+
+AdminServer::Connection::Connection() = default;
+
+AdminServer::Connection::~Connection() = default;
+
+AdminServer::AdminServer(std::unique_ptr<ServerSocket> server_socket,
+                         GetProfileCallback get_profile,
+                         SwitchProfileCallback switch_profile)
+    : server_socket_(std::move(server_socket)),
+      get_profile_(std::move(get_profile)),
+      switch_profile_(std::move(switch_profile)),
+      last_id_(0) {
+  DCHECK(server_socket_);
+  base::ThreadTaskRunnerHandle::Get()->PostTask(
+      FROM_HERE, base::BindOnce(&AdminServer::DoAcceptLoop,
+                                weak_ptr_factory_.GetWeakPtr()));
+}
+
+AdminServer::~AdminServer() = default;
+
+void AdminServer::DoAcceptLoop() {
+  int result;
+  do {
+    result = server_socket_->Accept(
+        &accepted_socket_, base::BindOnce(&AdminServer::OnAcceptComplete,
+                                          weak_ptr_factory_.GetWeakPtr()));
+    if (result == ERR_IO_PENDING)
+      return;
+    HandleAcceptResult(result);
+  } while (result == OK);
+}
+
+void AdminServer::OnAcceptComplete(int result) {
+  HandleAcceptResult(result);
+  if (result == OK)
+    DoAcceptLoop();
+}
+
+void AdminServer::HandleAcceptResult(int result) {
+  if (result != OK) {
+    LOG(ERROR) << "Admin accept error: rv=" << result;
+    return;
+  }
+  unsigned int connection_id = ++last_id_;
+  auto connection = std::make_unique<Connection>();
+  connection->socket = std::move(accepted_socket_);
+  connection->read_buffer = base::MakeRefCounted<IOBuffer>(kBufferSize);
+  connection_by_id_[connection_id] = std::move(connection);
+  DoRead(connection_id);
+}
+
+void AdminServer::DoRead(unsigned int connection_id) {
+  int result;
+  do {
+    Connection* connection = connection_by_id_[connection_id].get();
+    result = connection->socket->Read(
+        connection->read_buffer.get(), kBufferSize,
+        base::BindOnce(&AdminServer::OnReadComplete,
+                       weak_ptr_factory_.GetWeakPtr(), connection_id));
+    if (result == ERR_IO_PENDING)
+      return;
+  } while (HandleReadResult(connection_id, result));
+}
+
+void AdminServer::OnReadComplete(unsigned int connection_id, int result) {
+  if (HandleReadResult(connection_id, result))
+    DoRead(connection_id);
+}
+
+bool AdminServer::HandleReadResult(unsigned int connection_id, int result) {
+  auto it = connection_by_id_.find(connection_id);
+  if (it == connection_by_id_.end())
+    return false;
+  if (result <= 0) {
+    Close(connection_id);
+    return false;
+  }
+  Connection* connection = it->second.get();
+  connection->request.append(connection->read_buffer->data(), result);
+  size_t line_end = connection->request.find("\r\n");
+  if (line_end == std::string::npos) {
+    if (connection->request.size() >= kBufferSize) {
+      SendResponse(connection_id, "414 URI Too Long", "");
+      return false;
+    }
+    return true;
+  }
+  HandleRequest(connection_id,
+                base::StringPiece(connection->request).substr(0, line_end));
+  return false;
+}
+
+void AdminServer::HandleRequest(unsigned int connection_id,
+                                base::StringPiece request_line) {
+  std::vector<base::StringPiece> parts = base::SplitStringPiece(
+      request_line, " ", base::KEEP_WHITESPACE, base::SPLIT_WANT_NONEMPTY);
+  if (parts.size() != 3) {
+    SendResponse(connection_id, "400 Bad Request", "");
+    return;
+  }
+  base::StringPiece method = parts[0];
+  base::StringPiece path = parts[1];
+
+  if (method == "GET" && path == kProfilePath) {
+    SendResponse(connection_id, "200 OK", get_profile_.Run());
+    return;
+  }
+  if (method == "POST" && base::StartsWith(path, kProfilePathPrefix)) {
+    std::string profile(path.substr(kProfilePathPrefixSize));
+    if (!switch_profile_.Run(profile)) {
+      SendResponse(connection_id, "404 Not Found", "Unknown profile");
+      return;
+    }
+    SendResponse(connection_id, "200 OK", profile);
+    return;
+  }
+  SendResponse(connection_id, "404 Not Found", "");
+}
+
+void AdminServer::SendResponse(unsigned int connection_id,
+                               base::StringPiece status,
+                               base::StringPiece body) {
+  Connection* connection = connection_by_id_[connection_id].get();
+  std::string body_line = body.empty() ? "" : base::StrCat({body, "\n"});
+  std::string response = base::StrCat(
+      {"HTTP/1.1 ", status, "\r\nContent-Length: ",
+       base::NumberToString(body_line.size()),
+       "\r\nConnection: close\r\n\r\n", body_line});
+  connection->write_buffer = base::MakeRefCounted<DrainableIOBuffer>(
+      base::MakeRefCounted<StringIOBuffer>(response), response.size());
+  DoWrite(connection_id);
+}
+
+void AdminServer::DoWrite(unsigned int connection_id) {
+  Connection* connection = connection_by_id_[connection_id].get();
+  while (connection->write_buffer->BytesRemaining() > 0) {
+    int result = connection->socket->Write(
+        connection->write_buffer.get(),
+        connection->write_buffer->BytesRemaining(),
+        base::BindOnce(&AdminServer::OnWriteComplete,
+                       weak_ptr_factory_.GetWeakPtr(), connection_id),
+        kTrafficAnnotation);
+    if (result == ERR_IO_PENDING)
+      return;
+    if (result < 0)
+      break;
+    connection->write_buffer->DidConsume(result);
+  }
+  Close(connection_id);
+}
+
+void AdminServer::OnWriteComplete(unsigned int connection_id, int result) {
+  auto it = connection_by_id_.find(connection_id);
+  if (it == connection_by_id_.end())
+    return;
+  if (result < 0) {
+    Close(connection_id);
+    return;
+  }
+  it->second->write_buffer->DidConsume(result);
+  DoWrite(connection_id);
+}
+
+void AdminServer::Close(unsigned int connection_id) {
+  auto it = connection_by_id_.find(connection_id);
+  if (it == connection_by_id_.end())
+    return;
+  // May be called from the socket's own callback.
+  base::ThreadTaskRunnerHandle::Get()->DeleteSoon(FROM_HERE,
+                                                  std::move(it->second));
+  connection_by_id_.erase(it);
+}
+
+}  // namespace net
@@ -0,0 +1,87 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_ADMIN_SERVER_H_
+#define NET_TOOLS_NAIVE_ADMIN_SERVER_H_
+
+#include <map>
+#include <memory>
+#include <string>
+
+#include "base/callback.h"
+#include "base/macros.h"
+#include "base/memory/scoped_refptr.h"
+#include "base/memory/weak_ptr.h"
+#include "base/strings/string_piece.h"
+
+namespace net {
+
+class DrainableIOBuffer;
+class IOBuffer;
+class ServerSocket;
+class StreamSocket;
+
+// Answers local HTTP requests for switching profiles:
+//
+//   GET /profile          Returns the active profile.
+//   POST /profile/<name>  Switches to profile <name>.
+//
+// Each connection carries one request.
+class AdminServer {
+ public:
+  using GetProfileCallback = base::RepeatingCallback<std::string()>;
+  // Returns false if the profile is unknown.
+  using SwitchProfileCallback =
+      base::RepeatingCallback<bool(const std::string& profile)>;
+
+  AdminServer(std::unique_ptr<ServerSocket> server_socket,
+              GetProfileCallback get_profile,
+              SwitchProfileCallback switch_profile);
+  ~AdminServer();
+
+ private:
+  struct Connection {
+    Connection();
+    ~Connection();
+
+    std::unique_ptr<StreamSocket> socket;
+    scoped_refptr<IOBuffer> read_buffer;
+    std::string request;
+    scoped_refptr<DrainableIOBuffer> write_buffer;
+  };
+
+  void DoAcceptLoop();
+  void OnAcceptComplete(int result);
+  void HandleAcceptResult(int result);
+
+  void DoRead(unsigned int connection_id);
+  void OnReadComplete(unsigned int connection_id, int result);
+  // Returns true if more of the request line has to be read.
+  bool HandleReadResult(unsigned int connection_id, int result);
+
+  void HandleRequest(unsigned int connection_id,
+                     base::StringPiece request_line);
+  void SendResponse(unsigned int connection_id,
+                    base::StringPiece status,
+                    base::StringPiece body);
+  void DoWrite(unsigned int connection_id);
+  void OnWriteComplete(unsigned int connection_id, int result);
+
+  void Close(unsigned int connection_id);
+
+  std::unique_ptr<ServerSocket> server_socket_;
+  GetProfileCallback get_profile_;
+  SwitchProfileCallback switch_profile_;
+
+  unsigned int last_id_;
+  std::unique_ptr<StreamSocket> accepted_socket_;
+  std::map<unsigned int, std::unique_ptr<Connection>> connection_by_id_;
+
+  base::WeakPtrFactory<AdminServer> weak_ptr_factory_{this};
+
+  DISALLOW_COPY_AND_ASSIGN(AdminServer);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_ADMIN_SERVER_H_
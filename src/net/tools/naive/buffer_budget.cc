@@ -0,0 +1,158 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/buffer_budget.h"
+
+#include <utility>
+
+#include "base/check_op.h"
+#include "base/location.h"
+#include "base/logging.h"
+#include "base/threading/thread_task_runner_handle.h"
+
+namespace net {
+
+namespace {
+// How often stalled tunnels are looked for while requests are waiting.
+constexpr int kEvictionIntervalSeconds = 1;
+// Tunnels holding bytes without completing a write for this long may be
+// evicted.
+constexpr int kStallTimeoutSeconds = 10;
+}  // namespace
+
+BufferBudget::Request::Request(unsigned int id,
+                               int size,
+                               base::OnceClosure callback)
+    : id(id), size(size), callback(std::move(callback)) {}
+
+BufferBudget::Request::Request(Request&& other) = default;
+
+BufferBudget::Request& BufferBudget::Request::operator=(Request&& other) =
+    default;
+
+BufferBudget::Request::~Request() = default;
+
+BufferBudget::BufferBudget(int64_t limit,
+                           base::RepeatingCallback<void(unsigned int)> evict)
+    : limit_(limit), bytes_reserved_(0), evict_(std::move(evict)) {
+  DCHECK_GT(limit_, 0);
+}
+
+BufferBudget::~BufferBudget() = default;
+
+bool BufferBudget::Reserve(unsigned int id,
+                           int size,
+                           base::OnceClosure callback) {
+  DCHECK_GT(size, 0);
+  auto& tunnel = tunnels_[id];
+  if (waiting_requests_.empty() && bytes_reserved_ + size <= limit_) {
+    if (tunnel.bytes == 0)
+      tunnel.last_progress = base::TimeTicks::Now();
+    bytes_reserved_ += size;
+    tunnel.bytes += size;
+    return true;
+  }
+  waiting_requests_.emplace_back(id, size, std::move(callback));
+  if (!eviction_timer_.IsRunning()) {
+    eviction_timer_.Start(
+        FROM_HERE, base::TimeDelta::FromSeconds(kEvictionIntervalSeconds),
+        this, &BufferBudget::EvictStalled);
+  }
+  return false;
+}
+
+void BufferBudget::Release(unsigned int id, int size) {
+  auto it = tunnels_.find(id);
+  DCHECK(it != tunnels_.end());
+  it->second.bytes -= size;
+  DCHECK_GE(it->second.bytes, 0);
+  it->second.last_progress = base::TimeTicks::Now();
+  bytes_reserved_ -= size;
+  DCHECK_GE(bytes_reserved_, 0);
+  Schedule();
+}
+
+void BufferBudget::RemoveTunnel(unsigned int id) {
+  for (auto it = waiting_requests_.begin(); it != waiting_requests_.end();) {
+    if (it->id == id) {
+      it = waiting_requests_.erase(it);
+    } else {
+      ++it;
+    }
+  }
+  auto it = tunnels_.find(id);
+  if (it == tunnels_.end())
+    return;
+  bytes_reserved_ -= it->second.bytes;
+  DCHECK_GE(bytes_reserved_, 0);
+  tunnels_.erase(it);
+  Schedule();
+}
+
+void BufferBudget::Schedule() {
+  while (!waiting_requests_.empty() &&
+         bytes_reserved_ + waiting_requests_.front().size <= limit_) {
+    Request request = std::move(waiting_requests_.front());
+    waiting_requests_.pop_front();
+    Grant(std::move(request));
+  }
+  if (waiting_requests_.empty())
+    eviction_timer_.Stop();
+}
+
+void BufferBudget::Grant(Request request) {
+  auto& tunnel = tunnels_[request.id];
+  if (tunnel.bytes == 0)
+    tunnel.last_progress = base::TimeTicks::Now();
+  tunnel.bytes += request.size;
+  bytes_reserved_ += request.size;
+  // Posted so the tunnel doesn't read from within another tunnel's
+  // completion callback.
+  base::ThreadTaskRunnerHandle::Get()->PostTask(FROM_HERE,
+                                                std::move(request.callback));
+}
+
+void BufferBudget::EvictStalled() {
+  if (waiting_requests_.empty()) {
+    eviction_timer_.Stop();
+    return;
+  }
+
+  // Bytes still missing for the first request, counting those of tunnels
+  // already evicted but not yet removed as freed.
+  int64_t missing = bytes_reserved_ + waiting_requests_.front().size - limit_;
+  for (const auto& item : tunnels_) {
+    if (item.second.evicted)
+      missing -= item.second.bytes;
+  }
+
+  base::TimeTicks stalled_before =
+      base::TimeTicks::Now() -
+      base::TimeDelta::FromSeconds(kStallTimeoutSeconds);
+  while (missing > 0) {
+    // Evicts the tunnel whose writes have been stuck the longest.
+    auto victim = tunnels_.end();
+    for (auto it = tunnels_.begin(); it != tunnels_.end(); ++it) {
+      const Tunnel& tunnel = it->second;
+      if (tunnel.evicted || tunnel.bytes == 0 ||
+          tunnel.last_progress > stalled_before) {
+        continue;
+      }
+      if (victim == tunnels_.end() ||
+          tunnel.last_progress < victim->second.last_progress) {
+        victim = it;
+      }
+    }
+    if (victim == tunnels_.end())
+      return;
+
+    victim->second.evicted = true;
+    missing -= victim->second.bytes;
+    LOG(WARNING) << "Connection " << victim->first << " evicted holding "
+                 << victim->second.bytes << " bytes of buffers";
+    evict_.Run(victim->first);
+  }
+}
+
+}  // namespace net
@@ -0,0 +1,82 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_BUFFER_BUDGET_H_
+#define NET_TOOLS_NAIVE_BUFFER_BUDGET_H_
+
+#include <cstdint>
+#include <deque>
+#include <map>
+
+#include "base/callback.h"
+#include "base/macros.h"
+#include "base/time/time.h"
+#include "base/timer/timer.h"
+
+namespace net {
+
+// Bounds the memory of data held in tunnels across all tunnels. A tunnel
+// reserves the bytes it has read before writing them on and returns them once
+// written, so idle tunnels hold nothing. When the budget is used up tunnels
+// hold their data and stop reading until bytes are returned, which pushes
+// back on the faster side. While tunnels are waiting, tunnels whose writes
+// have not completed for too long, e.g. behind stuck clients, are evicted to
+// free their bytes.
+class BufferBudget {
+ public:
+  // |evict| is called with the id of a tunnel that should be closed. Its
+  // buffers are returned when it is removed.
+  BufferBudget(int64_t limit,
+               base::RepeatingCallback<void(unsigned int)> evict);
+  ~BufferBudget();
+
+  // Returns true if tunnel |id| may take |size| bytes right away. Otherwise
+  // returns false and posts |callback| once the bytes are reserved.
+  bool Reserve(unsigned int id, int size, base::OnceClosure callback);
+
+  // Returns |size| bytes reserved by tunnel |id|.
+  void Release(unsigned int id, int size);
+
+  // Drops the requests of tunnel |id| and returns all its bytes.
+  void RemoveTunnel(unsigned int id);
+
+ private:
+  struct Request {
+    Request(unsigned int id, int size, base::OnceClosure callback);
+    Request(Request&& other);
+    Request& operator=(Request&& other);
+    ~Request();
+
+    unsigned int id;
+    int size;
+    base::OnceClosure callback;
+  };
+
+  struct Tunnel {
+    int64_t bytes = 0;
+    // Last time the tunnel returned bytes or started holding bytes, so no
+    // write of held bytes has completed since.
+    base::TimeTicks last_progress;
+    bool evicted = false;
+  };
+
+  void Schedule();
+  void Grant(Request request);
+  void EvictStalled();
+
+  int64_t limit_;
+  int64_t bytes_reserved_;
+  // First come, first served.
+  std::deque<Request> waiting_requests_;
+  std::map<unsigned int, Tunnel> tunnels_;
+
+  // Runs while there are waiting requests.
+  base::RepeatingTimer eviction_timer_;
+  base::RepeatingCallback<void(unsigned int)> evict_;
+
+  DISALLOW_COPY_AND_ASSIGN(BufferBudget);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_BUFFER_BUDGET_H_
@@ -0,0 +1,377 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/compressed_socket.h"
+
+#include <algorithm>
+#include <cstring>
+#include <utility>
+
+#include "base/bind.h"
+#include "base/check_op.h"
+#include "net/base/net_errors.h"
+#include "third_party/zlib/zlib.h"
+
+namespace net {
+
+namespace {
+constexpr int kBufferSize = 64 * 1024;
+// One byte of frame type followed by three bytes of payload length.
+constexpr int kFrameHeaderSize = 4;
+constexpr uint8_t kFrameRaw = 0;
+constexpr uint8_t kFrameDeflate = 1;
+// Applies to both the payload and the decompressed payload of a frame.
+constexpr int kMaxFrameSize = 1024 * 1024;
+// Smaller writes are mostly interactive and not worth compressing.
+constexpr int kMinCompressSize = 256;
+constexpr int kMaxIncompressibleFrames = 4;
+constexpr int kWindowBits = 15;
+constexpr int kMemLevel = 8;
+}  // namespace
+
+CompressedSocket::CompressedSocket(
+    StreamSocket* transport_socket,
+    const NetworkTrafficAnnotationTag& traffic_annotation)
+    : transport_(transport_socket),
+      incompressible_frames_(0),
+      raw_frames_read_(0),
+      user_read_buf_len_(0),
+      user_write_size_(0),
+      bytes_written_(0),
+      wire_bytes_written_(0),
+      bytes_read_(0),
+      wire_bytes_read_(0),
+      traffic_annotation_(traffic_annotation) {}
+
+CompressedSocket::~CompressedSocket() {
+  FreeDeflateStream();
+  FreeInflateStream();
+}
+
+int CompressedSocket::Connect(CompletionOnceCallback callback) {
+  return transport_->Connect(std::move(callback));
+}
+
+void CompressedSocket::Disconnect() {
+  transport_->Disconnect();
+  weak_ptr_factory_.InvalidateWeakPtrs();
+  read_callback_.Reset();
+  write_callback_.Reset();
+}
+
+bool CompressedSocket::IsConnected() const {
+  return transport_->IsConnected();
+}
+
+bool CompressedSocket::IsConnectedAndIdle() const {
+  return read_output_.empty() && transport_->IsConnectedAndIdle();
+}
+
+const NetLogWithSource& CompressedSocket::NetLog() const {
+  return transport_->NetLog();
+}
+
+bool CompressedSocket::WasEverUsed() const {
+  return transport_->WasEverUsed();
+}
+
+bool CompressedSocket::WasAlpnNegotiated() const {
+  return transport_->WasAlpnNegotiated();
+}
+
+NextProto CompressedSocket::GetNegotiatedProtocol() const {
+  return transport_->GetNegotiatedProtocol();
+}
+
+bool CompressedSocket::GetSSLInfo(SSLInfo* ssl_info) {
+  return transport_->GetSSLInfo(ssl_info);
+}
+
+void CompressedSocket::GetConnectionAttempts(ConnectionAttempts* out) const {
+  out->clear();
+}
+
+int64_t CompressedSocket::GetTotalReceivedBytes() const {
+  return transport_->GetTotalReceivedBytes();
+}
+
+void CompressedSocket::ApplySocketTag(const SocketTag& tag) {
+  return transport_->ApplySocketTag(tag);
+}
+
+int CompressedSocket::Read(IOBuffer* buf,
+                           int buf_len,
+                           CompletionOnceCallback callback) {
+  DCHECK(!read_callback_);
+  DCHECK(callback);
+
+  if (!read_output_.empty())
+    return CopyOutput(buf, buf_len);
+
+  user_read_buf_ = buf;
+  user_read_buf_len_ = buf_len;
+  int rv = DoTransportRead();
+  if (rv == ERR_IO_PENDING) {
+    read_callback_ = std::move(callback);
+  } else {
+    user_read_buf_ = nullptr;
+  }
+  return rv;
+}
+
+int CompressedSocket::DoTransportRead() {
+  while (true) {
+    transport_read_buf_ = base::MakeRefCounted<IOBuffer>(kBufferSize);
+    int rv = transport_->Read(
+        transport_read_buf_.get(), kBufferSize,
+        base::BindOnce(&CompressedSocket::OnTransportReadComplete,
+                       weak_ptr_factory_.GetWeakPtr()));
+    if (rv == ERR_IO_PENDING)
+      return rv;
+    rv = HandleTransportRead(rv);
+    if (rv != ERR_IO_PENDING)
+      return rv;
+  }
+}
+
+int CompressedSocket::HandleTransportRead(int result) {
+  if (result < 0)
+    return result;
+
+  if (result == 0) {
+    // A truncated frame is an error rather than a clean close.
+    return read_input_.empty() ? 0 : ERR_CONNECTION_CLOSED;
+  }
+
+  wire_bytes_read_ += result;
+  read_input_.append(transport_read_buf_->data(), result);
+  transport_read_buf_ = nullptr;
+
+  int rv = DecodeFrames();
+  if (rv < 0)
+    return rv;
+  if (read_output_.empty())
+    return ERR_IO_PENDING;
+  return CopyOutput(user_read_buf_.get(), user_read_buf_len_);
+}
+
+void CompressedSocket::OnTransportReadComplete(int result) {
+  DCHECK(read_callback_);
+  int rv = HandleTransportRead(result);
+  if (rv == ERR_IO_PENDING)
+    rv = DoTransportRead();
+  if (rv == ERR_IO_PENDING)
+    return;
+  user_read_buf_ = nullptr;
+  std::move(read_callback_).Run(rv);
+}
+
+int CompressedSocket::DecodeFrames() {
+  while (read_input_.size() >= kFrameHeaderSize) {
+    const auto* p = reinterpret_cast<const uint8_t*>(read_input_.data());
+    uint8_t type = p[0];
+    int size = (p[1] << 16) | (p[2] << 8) | p[3];
+    if ((type != kFrameRaw && type != kFrameDeflate) || size > kMaxFrameSize)
+      return ERR_CONTENT_DECODING_FAILED;
+    if (read_input_.size() < static_cast<size_t>(kFrameHeaderSize + size))
+      break;
+
+    const char* payload = read_input_.data() + kFrameHeaderSize;
+    size_t output_size = read_output_.size();
+    if (type == kFrameRaw) {
+      read_output_.append(payload, size);
+      // The other end has likely given up on compression.
+      if (size >= kMinCompressSize &&
+          ++raw_frames_read_ >= kMaxIncompressibleFrames) {
+        FreeInflateStream();
+      }
+    } else if (!Decompress(payload, size, &read_output_)) {
+      return ERR_CONTENT_DECODING_FAILED;
+    }
+    bytes_read_ += read_output_.size() - output_size;
+    read_input_.erase(0, kFrameHeaderSize + size);
+  }
+  return OK;
+}
+
+int CompressedSocket::CopyOutput(IOBuffer* buf, int buf_len) {
+  int size = std::min(static_cast<int>(read_output_.size()), buf_len);
+  std::memcpy(buf->data(), read_output_.data(), size);
+  read_output_.erase(0, size);
+  return size;
+}
+
+int CompressedSocket::Write(
+    IOBuffer* buf,
+    int buf_len,
+    CompletionOnceCallback callback,
+    const NetworkTrafficAnnotationTag& traffic_annotation) {
+  DCHECK(!write_callback_);
+  DCHECK(callback);
+  DCHECK_GT(buf_len, 0);
+  DCHECK_LE(buf_len, kMaxFrameSize);
+
+  std::string compressed;
+  uint8_t type = kFrameRaw;
+  if (incompressible_frames_ < kMaxIncompressibleFrames &&
+      buf_len >= kMinCompressSize) {
+    if (Compress(buf->data(), buf_len, &compressed)) {
+      type = kFrameDeflate;
+      incompressible_frames_ = 0;
+    } else if (++incompressible_frames_ >= kMaxIncompressibleFrames) {
+      FreeDeflateStream();
+    }
+  }
+  const char* payload = type == kFrameDeflate ? compressed.data() : buf->data();
+  int size = type == kFrameDeflate ? compressed.size() : buf_len;
+
+  int frame_size = kFrameHeaderSize + size;
+  auto frame = base::MakeRefCounted<IOBuffer>(frame_size);
+  auto* p = reinterpret_cast<uint8_t*>(frame->data());
+  p[0] = type;
+  p[1] = size >> 16;
+  p[2] = size >> 8;
+  p[3] = size;
+  std::memcpy(p + kFrameHeaderSize, payload, size);
+  write_buf_ =
+      base::MakeRefCounted<DrainableIOBuffer>(std::move(frame), frame_size);
+  user_write_size_ = buf_len;
+  bytes_written_ += buf_len;
+  wire_bytes_written_ += frame_size;
+
+  int rv = DoTransportWrite();
+  if (rv == ERR_IO_PENDING)
+    write_callback_ = std::move(callback);
+  return rv;
+}
+
+int CompressedSocket::DoTransportWrite() {
+  while (write_buf_->BytesRemaining() > 0) {
+    int rv = transport_->Write(
+        write_buf_.get(), write_buf_->BytesRemaining(),
+        base::BindOnce(&CompressedSocket::OnTransportWriteComplete,
+                       weak_ptr_factory_.GetWeakPtr()),
+        traffic_annotation_);
+    if (rv == ERR_IO_PENDING)
+      return rv;
+    if (rv < 0) {
+      write_buf_ = nullptr;
+      return rv;
+    }
+    write_buf_->DidConsume(rv);
+  }
+  write_buf_ = nullptr;
+  // The whole frame is written, which covers all bytes from above.
+  return user_write_size_;
+}
+
+void CompressedSocket::OnTransportWriteComplete(int result) {
+  DCHECK(write_callback_);
+  if (result >= 0) {
+    write_buf_->DidConsume(result);
+    result = DoTransportWrite();
+    if (result == ERR_IO_PENDING)
+      return;
+  } else {
+    write_buf_ = nullptr;
+  }
+  std::move(write_callback_).Run(result);
+}
+
+bool CompressedSocket::Compress(const char* data,
+                                int size,
+                                std::string* out) {
+  if (!deflate_stream_) {
+    deflate_stream_ = std::make_unique<z_stream>();
+    std::memset(deflate_stream_.get(), 0, sizeof(*deflate_stream_));
+    // Raw deflate without zlib headers, as frames carry the lengths.
+    int result = deflateInit2(deflate_stream_.get(), Z_BEST_SPEED, Z_DEFLATED,
+                              -kWindowBits, kMemLevel, Z_DEFAULT_STRATEGY);
+    if (result != Z_OK) {
+      // Sends the rest of the tunnel uncompressed.
+      deflate_stream_.reset();
+      incompressible_frames_ = kMaxIncompressibleFrames;
+      return false;
+    }
+  }
+  z_stream* stream = deflate_stream_.get();
+  if (deflateReset(stream) != Z_OK)
+    return false;
+  out->resize(deflateBound(stream, size));
+  stream->next_in = reinterpret_cast<Bytef*>(const_cast<char*>(data));
+  stream->avail_in = size;
+  stream->next_out = reinterpret_cast<Bytef*>(&(*out)[0]);
+  stream->avail_out = out->size();
+  if (deflate(stream, Z_FINISH) != Z_STREAM_END)
+    return false;
+  out->resize(out->size() - stream->avail_out);
+  // Saving less than 10% is not worth the CPU on the other end.
+  return out->size() * 10 < static_cast<size_t>(size) * 9;
+}
+
+bool CompressedSocket::Decompress(const char* data,
+                                  int size,
+                                  std::string* out) {
+  raw_frames_read_ = 0;
+  if (!inflate_stream_) {
+    inflate_stream_ = std::make_unique<z_stream>();
+    std::memset(inflate_stream_.get(), 0, sizeof(*inflate_stream_));
+    int result = inflateInit2(inflate_stream_.get(), -kWindowBits);
+    if (result != Z_OK) {
+      // The frame cannot be decoded, which fails the connection.
+      inflate_stream_.reset();
+      return false;
+    }
+  }
+  z_stream* stream = inflate_stream_.get();
+  if (inflateReset(stream) != Z_OK)
+    return false;
+  stream->next_in = reinterpret_cast<Bytef*>(const_cast<char*>(data));
+  stream->avail_in = size;
+  size_t start = out->size();
+  int result;
+  do {
+    size_t offset = out->size();
+    if (offset - start >= static_cast<size_t>(kMaxFrameSize))
+      return false;
+    out->resize(offset + kBufferSize);
+    stream->next_out = reinterpret_cast<Bytef*>(&(*out)[offset]);
+    stream->avail_out = kBufferSize;
+    result = inflate(stream, Z_NO_FLUSH);
+    out->resize(out->size() - stream->avail_out);
+  } while (result == Z_OK);
+  return result == Z_STREAM_END && stream->avail_in == 0;
+}
+
+void CompressedSocket::FreeDeflateStream() {
+  if (!deflate_stream_)
+    return;
+  deflateEnd(deflate_stream_.get());
+  deflate_stream_.reset();
+}
+
+void CompressedSocket::FreeInflateStream() {
+  if (!inflate_stream_)
+    return;
+  inflateEnd(inflate_stream_.get());
+  inflate_stream_.reset();
+}
+
+int CompressedSocket::SetReceiveBufferSize(int32_t size) {
+  return transport_->SetReceiveBufferSize(size);
+}
+
+int CompressedSocket::SetSendBufferSize(int32_t size) {
+  return transport_->SetSendBufferSize(size);
+}
+
+int CompressedSocket::GetPeerAddress(IPEndPoint* address) const {
+  return transport_->GetPeerAddress(address);
+}
+
+int CompressedSocket::GetLocalAddress(IPEndPoint* address) const {
+  return transport_->GetLocalAddress(address);
+}
+
+}  // namespace net
@@ -0,0 +1,137 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_COMPRESSED_SOCKET_H_
+#define NET_TOOLS_NAIVE_COMPRESSED_SOCKET_H_
+
+#include <cstdint>
+#include <memory>
+#include <string>
+
+#include "base/macros.h"
+#include "base/memory/scoped_refptr.h"
+#include "base/memory/weak_ptr.h"
+#include "net/base/completion_once_callback.h"
+#include "net/base/io_buffer.h"
+#include "net/base/ip_endpoint.h"
+#include "net/log/net_log_with_source.h"
+#include "net/socket/connection_attempts.h"
+#include "net/socket/next_proto.h"
+#include "net/socket/stream_socket.h"
+#include "net/ssl/ssl_info.h"
+
+extern "C" struct z_stream_s;
+
+namespace net {
+struct NetworkTrafficAnnotationTag;
+
+// This StreamSocket compresses a tunnel to another naive with deflate.
+// Each write is sent as one frame, compressed on its own, or sent as is if
+// compression does not pay off. Compression is given up for the rest of the
+// tunnel after a few frames in a row that do not compress, which is what
+// encrypted or already compressed flows look like. Compression state is only
+// kept while a direction is compressing.
+class CompressedSocket : public StreamSocket {
+ public:
+  // |transport_socket| is not owned and must outlive this socket.
+  CompressedSocket(StreamSocket* transport_socket,
+                   const NetworkTrafficAnnotationTag& traffic_annotation);
+
+  ~CompressedSocket() override;
+
+  // Payload bytes written by the layer above.
+  int64_t bytes_written() const { return bytes_written_; }
+  // Frame bytes written to the transport.
+  int64_t wire_bytes_written() const { return wire_bytes_written_; }
+  // Payload bytes decoded for the layer above.
+  int64_t bytes_read() const { return bytes_read_; }
+  // Frame bytes read from the transport.
+  int64_t wire_bytes_read() const { return wire_bytes_read_; }
+
+  // StreamSocket implementation.
+  int Connect(CompletionOnceCallback callback) override;
+  void Disconnect() override;
+  bool IsConnected() const override;
+  bool IsConnectedAndIdle() const override;
+  const NetLogWithSource& NetLog() const override;
+  bool WasEverUsed() const override;
+  bool WasAlpnNegotiated() const override;
+  NextProto GetNegotiatedProtocol() const override;
+  bool GetSSLInfo(SSLInfo* ssl_info) override;
+  void GetConnectionAttempts(ConnectionAttempts* out) const override;
+  void ClearConnectionAttempts() override {}
+  void AddConnectionAttempts(const ConnectionAttempts& attempts) override {}
+  int64_t GetTotalReceivedBytes() const override;
+  void ApplySocketTag(const SocketTag& tag) override;
+
+  // Socket implementation.
+  int Read(IOBuffer* buf,
+           int buf_len,
+           CompletionOnceCallback callback) override;
+  int Write(IOBuffer* buf,
+            int buf_len,
+            CompletionOnceCallback callback,
+            const NetworkTrafficAnnotationTag& traffic_annotation) override;
+
+  int SetReceiveBufferSize(int32_t size) override;
+  int SetSendBufferSize(int32_t size) override;
+
+  int GetPeerAddress(IPEndPoint* address) const override;
+  int GetLocalAddress(IPEndPoint* address) const override;
+
+ private:
+  int DoTransportRead();
+  // Returns ERR_IO_PENDING if more data is needed from the transport.
+  int HandleTransportRead(int result);
+  void OnTransportReadComplete(int result);
+  int DecodeFrames();
+  int CopyOutput(IOBuffer* buf, int buf_len);
+
+  int DoTransportWrite();
+  void OnTransportWriteComplete(int result);
+  // Returns false if |data| does not compress well.
+  bool Compress(const char* data, int size, std::string* out);
+  bool Decompress(const char* data, int size, std::string* out);
+  void FreeDeflateStream();
+  void FreeInflateStream();
+
+  StreamSocket* transport_;
+
+  // Created on first use and freed once the direction stops compressing, as
+  // each takes a few hundred KB.
+  std::unique_ptr<z_stream_s> deflate_stream_;
+  std::unique_ptr<z_stream_s> inflate_stream_;
+  // Frames in a row that did not compress well.
+  int incompressible_frames_;
+  // Large raw frames read in a row.
+  int raw_frames_read_;
+
+  // Frame bytes not yet decoded.
+  std::string read_input_;
+  // Decoded bytes not yet read by the layer above.
+  std::string read_output_;
+  scoped_refptr<IOBuffer> transport_read_buf_;
+  scoped_refptr<IOBuffer> user_read_buf_;
+  int user_read_buf_len_;
+  CompletionOnceCallback read_callback_;
+
+  scoped_refptr<DrainableIOBuffer> write_buf_;
+  int user_write_size_;
+  CompletionOnceCallback write_callback_;
+
+  int64_t bytes_written_;
+  int64_t wire_bytes_written_;
+  int64_t bytes_read_;
+  int64_t wire_bytes_read_;
+
+  // Traffic annotation for socket control.
+  const NetworkTrafficAnnotationTag& traffic_annotation_;
+
+  base::WeakPtrFactory<CompressedSocket> weak_ptr_factory_{this};
+
+  DISALLOW_COPY_AND_ASSIGN(CompressedSocket);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_COMPRESSED_SOCKET_H_
@@ -0,0 +1,237 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#include "net/tools/naive/dscp_socket_factory.h"
+
+#include <utility>
+
+#include "base/bind.h"
+#include "base/logging.h"
+#include "base/strings/strcat.h"
+#include "base/strings/string_number_conversions.h"
+#include "base/strings/string_split.h"
+#include "base/strings/string_util.h"
+#include "net/base/address_list.h"
+#include "net/base/ip_endpoint.h"
+#include "net/base/net_errors.h"
+#include "net/socket/datagram_client_socket.h"
+#include "net/socket/ssl_client_socket.h"
+#include "net/socket/udp_client_socket.h"
+
+namespace net {
+namespace {
+constexpr int kMaxDscp = 63;
+
+struct DscpName {
+  const char* name;
+  DiffServCodePoint dscp;
+};
+
+constexpr DscpName kDscpNames[] = {
+    {"cs0", DSCP_CS0},   {"cs1", DSCP_CS1},   {"af11", DSCP_AF11},
+    {"af12", DSCP_AF12}, {"af13", DSCP_AF13}, {"cs2", DSCP_CS2},
+    {"af21", DSCP_AF21}, {"af22", DSCP_AF22}, {"af23", DSCP_AF23},
+    {"cs3", DSCP_CS3},   {"af31", DSCP_AF31}, {"af32", DSCP_AF32},
+    {"af33", DSCP_AF33}, {"cs4", DSCP_CS4},   {"af41", DSCP_AF41},
+    {"af42", DSCP_AF42}, {"af43", DSCP_AF43}, {"cs5", DSCP_CS5},
+    {"ef", DSCP_EF},     {"cs6", DSCP_CS6},   {"cs7", DSCP_CS7},
+};
+
+// Runs |mark_callback| once connected, as UDP sockets are only opened by
+// connecting.
+class DscpUDPClientSocket : public UDPClientSocket {
+ public:
+  using MarkCallback =
+      base::RepeatingCallback<void(UDPClientSocket*, const IPEndPoint&)>;
+
+  DscpUDPClientSocket(DatagramSocket::BindType bind_type,
+                      net::NetLog* net_log,
+                      const NetLogSource& source,
+                      const MarkCallback& mark_callback)
+      : UDPClientSocket(bind_type, net_log, source),
+        mark_callback_(mark_callback) {}
+
+  int Connect(const IPEndPoint& address) override {
+    return Mark(UDPClientSocket::Connect(address), address);
+  }
+
+  int ConnectUsingNetwork(NetworkChangeNotifier::NetworkHandle network,
+                          const IPEndPoint& address) override {
+    return Mark(UDPClientSocket::ConnectUsingNetwork(network, address),
+                address);
+  }
+
+  int ConnectUsingDefaultNetwork(const IPEndPoint& address) override {
+    return Mark(UDPClientSocket::ConnectUsingDefaultNetwork(address),
+                address);
+  }
+
+ private:
+  int Mark(int result, const IPEndPoint& address) {
+    if (result == OK)
+      mark_callback_.Run(this, address);
+    return result;
+  }
+
+  MarkCallback mark_callback_;
+
+  DISALLOW_COPY_AND_ASSIGN(DscpUDPClientSocket);
+};
+}  // namespace
+
+bool ParseDiffServCodePoint(base::StringPiece str, DiffServCodePoint* dscp) {
+  int value;
+  if (base::StringToInt(str, &value)) {
+    if (value < 0 || value > kMaxDscp)
+      return false;
+    *dscp = static_cast<DiffServCodePoint>(value);
+    return true;
+  }
+  for (const auto& item : kDscpNames) {
+    if (base::EqualsCaseInsensitiveASCII(str, item.name)) {
+      *dscp = item.dscp;
+      return true;
+    }
+  }
+  return false;
+}
+
+bool ParseDscpRules(base::StringPiece str, std::vector<DscpRule>* rules) {
+  rules->clear();
+  for (base::StringPiece item : base::SplitStringPiece(
+           str, ",", base::TRIM_WHITESPACE, base::SPLIT_WANT_NONEMPTY)) {
+    std::vector<base::StringPiece> parts = base::SplitStringPiece(
+        item, base::kWhitespaceASCII, base::TRIM_WHITESPACE,
+        base::SPLIT_WANT_NONEMPTY);
+    if (parts.size() != 2)
+      return false;
+    DscpRule rule;
+    if (!ParseDiffServCodePoint(parts[0], &rule.dscp))
+      return false;
+    if (parts[1].find('/') != base::StringPiece::npos) {
+      if (!ParseCIDRBlock(std::string(parts[1]), &rule.prefix,
+                          &rule.prefix_length)) {
+        return false;
+      }
+    } else {
+      if (!rule.prefix.AssignFromIPLiteral(parts[1]))
+        return false;
+      rule.prefix_length = rule.prefix.size() * 8;
+    }
+    rules->push_back(rule);
+  }
+  return true;
+}
+
+std::string DscpRulesToString(const std::vector<DscpRule>& rules) {
+  std::vector<std::string> items;
+  for (const auto& rule : rules) {
+    items.push_back(base::StrCat({base::NumberToString(rule.dscp), " ",
+                                  rule.prefix.ToString(), "/",
+                                  base::NumberToString(rule.prefix_length)}));
+  }
+  return base::JoinString(items, ", ");
+}
+
+DscpSocketFactory::DscpSocketFactory(DiffServCodePoint dscp,
+                                     std::vector<DscpRule> rules)
+    : default_factory_(ClientSocketFactory::GetDefaultFactory()),
+      dscp_(dscp),
+      rules_(std::move(rules)),
+      logged_failure_(false) {}
+
+DscpSocketFactory::~DscpSocketFactory() = default;
+
+std::unique_ptr<DatagramClientSocket>
+DscpSocketFactory::CreateDatagramClientSocket(
+    DatagramSocket::BindType bind_type,
+    NetLog* net_log,
+    const NetLogSource& source) {
+  // The callback is owned by the socket, and this factory outlives all
+  // sockets.
+  return std::make_unique<DscpUDPClientSocket>(
+      bind_type, net_log, source,
+      base::BindRepeating(&DscpSocketFactory::OnDatagramConnected,
+                          base::Unretained(this)));
+}
+
+std::unique_ptr<TransportClientSocket>
+DscpSocketFactory::CreateTransportClientSocket(
+    const AddressList& addresses,
+    std::unique_ptr<SocketPerformanceWatcher> socket_performance_watcher,
+    NetworkQualityEstimator* network_quality_estimator,
+    NetLog* net_log,
+    const NetLogSource& source) {
+  auto socket = default_factory_->CreateTransportClientSocket(
+      addresses, std::move(socket_performance_watcher),
+      network_quality_estimator, net_log, source);
+  // The address being tried is not known before connecting, so the first
+  // address with a rule decides for all addresses of the host.
+  DiffServCodePoint dscp = dscp_;
+  for (const auto& endpoint : addresses) {
+    dscp = GetDscp(endpoint.address());
+    if (dscp != dscp_)
+      break;
+  }
+  // The callback is owned by |socket|, and this factory outlives all sockets.
+  // It runs after each platform socket is opened, before it connects.
+  socket->SetBeforeConnectCallback(base::BindRepeating(
+      &DscpSocketFactory::OnBeforeConnect, base::Unretained(this),
+      base::Unretained(socket.get()), dscp));
+  return socket;
+}
+
+std::unique_ptr<SSLClientSocket> DscpSocketFactory::CreateSSLClientSocket(
+    SSLClientContext* context,
+    std::unique_ptr<StreamSocket> stream_socket,
+    const HostPortPair& host_and_port,
+    const SSLConfig& ssl_config) {
+  return default_factory_->CreateSSLClientSocket(
+      context, std::move(stream_socket), host_and_port, ssl_config);
+}
+
+std::unique_ptr<ProxyClientSocket> DscpSocketFactory::CreateProxyClientSocket(
+    std::unique_ptr<StreamSocket> stream_socket,
+    const std::string& user_agent,
+    const HostPortPair& endpoint,
+    const ProxyServer& proxy_server,
+    HttpAuthController* http_auth_controller,
+    bool tunnel,
+    bool using_spdy,
+    NextProto negotiated_protocol,
+    ProxyDelegate* proxy_delegate,
+    const NetworkTrafficAnnotationTag& traffic_annotation) {
+  return default_factory_->CreateProxyClientSocket(
+      std::move(stream_socket), user_agent, endpoint, proxy_server,
+      http_auth_controller, tunnel, using_spdy, negotiated_protocol,
+      proxy_delegate, traffic_annotation);
+}
+
+DiffServCodePoint DscpSocketFactory::GetDscp(const IPAddress& address) const {
+  for (const auto& rule : rules_) {
+    if (IPAddressMatchesPrefix(address, rule.prefix, rule.prefix_length))
+      return rule.dscp;
+  }
+  return dscp_;
+}
+
+int DscpSocketFactory::OnBeforeConnect(TransportClientSocket* socket,
+                                       DiffServCodePoint dscp) {
+  HandleMarkResult(socket->SetDiffServCodePoint(dscp));
+  // Connects anyway without the marking.
+  return OK;
+}
+
+void DscpSocketFactory::OnDatagramConnected(UDPClientSocket* socket,
+                                            const IPEndPoint& address) {
+  HandleMarkResult(socket->SetDiffServCodePoint(GetDscp(address.address())));
+}
+
+void DscpSocketFactory::HandleMarkResult(int result) {
+  if (result != OK && !logged_failure_) {
+    LOG(WARNING) << "Failed to set DSCP: " << ErrorToShortString(result);
+    logged_failure_ = true;
+  }
+}
+
+}  // namespace net
@@ -0,0 +1,95 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#ifndef NET_TOOLS_NAIVE_DSCP_SOCKET_FACTORY_H_
+#define NET_TOOLS_NAIVE_DSCP_SOCKET_FACTORY_H_
+
+#include <memory>
+#include <string>
+#include <vector>
+
+#include "base/macros.h"
+#include "base/strings/string_piece.h"
+#include "net/base/ip_address.h"
+#include "net/socket/client_socket_factory.h"
+#include "net/socket/diff_serv_code_point.h"
+
+namespace net {
+
+class IPEndPoint;
+class UDPClientSocket;
+
+// Marks connections to addresses in a prefix with |dscp|.
+struct DscpRule {
+  DiffServCodePoint dscp;
+  IPAddress prefix;
+  size_t prefix_length;
+};
+
+// Parses a DSCP value given as a number from 0 to 63 or as a name like "ef",
+// "cs1" or "af41".
+bool ParseDiffServCodePoint(base::StringPiece str, DiffServCodePoint* dscp);
+
+// Parses comma separated rules of a DSCP value and an address or CIDR block,
+// e.g. "ef 192.0.2.0/24, cs1 2001:db8::1".
+bool ParseDscpRules(base::StringPiece str, std::vector<DscpRule>* rules);
+
+// Formats |rules| as accepted by ParseDscpRules().
+std::string DscpRulesToString(const std::vector<DscpRule>& rules);
+
+// Marks the TCP and UDP sockets it creates with a DSCP value, so routers can
+// prioritize proxy traffic, including QUIC. The first rule matching the
+// destination decides the value, otherwise |dscp| is used. Everything else is
+// passed to the default factory.
+class DscpSocketFactory : public ClientSocketFactory {
+ public:
+  DscpSocketFactory(DiffServCodePoint dscp, std::vector<DscpRule> rules);
+  ~DscpSocketFactory() override;
+
+  std::unique_ptr<DatagramClientSocket> CreateDatagramClientSocket(
+      DatagramSocket::BindType bind_type,
+      NetLog* net_log,
+      const NetLogSource& source) override;
+
+  std::unique_ptr<TransportClientSocket> CreateTransportClientSocket(
+      const AddressList& addresses,
+      std::unique_ptr<SocketPerformanceWatcher> socket_performance_watcher,
+      NetworkQualityEstimator* network_quality_estimator,
+      NetLog* net_log,
+      const NetLogSource& source) override;
+
+  std::unique_ptr<SSLClientSocket> CreateSSLClientSocket(
+      SSLClientContext* context,
+      std::unique_ptr<StreamSocket> stream_socket,
+      const HostPortPair& host_and_port,
+      const SSLConfig& ssl_config) override;
+
+  std::unique_ptr<ProxyClientSocket> CreateProxyClientSocket(
+      std::unique_ptr<StreamSocket> stream_socket,
+      const std::string& user_agent,
+      const HostPortPair& endpoint,
+      const ProxyServer& proxy_server,
+      HttpAuthController* http_auth_controller,
+      bool tunnel,
+      bool using_spdy,
+      NextProto negotiated_protocol,
+      ProxyDelegate* proxy_delegate,
+      const NetworkTrafficAnnotationTag& traffic_annotation) override;
+
+ private:
+  DiffServCodePoint GetDscp(const IPAddress& address) const;
+  int OnBeforeConnect(TransportClientSocket* socket, DiffServCodePoint dscp);
+  void OnDatagramConnected(UDPClientSocket* socket, const IPEndPoint& address);
+  void HandleMarkResult(int result);
+
+  ClientSocketFactory* const default_factory_;
+  const DiffServCodePoint dscp_;
+  const std::vector<DscpRule> rules_;
+  // Failures are only logged once, as they are likely to repeat.
+  bool logged_failure_;
+
+  DISALLOW_COPY_AND_ASSIGN(DscpSocketFactory);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_DSCP_SOCKET_FACTORY_H_
@@ -0,0 +1,82 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#include "net/tools/naive/host_log.h"
+
+#include "base/notreached.h"
+#include "base/rand_util.h"
+#include "base/strings/strcat.h"
+#include "base/strings/string_number_conversions.h"
+#include "base/strings/string_util.h"
+#include "crypto/sha2.h"
+#include "net/base/host_port_pair.h"
+
+namespace net {
+namespace {
+constexpr size_t kSaltSize = 16;
+constexpr size_t kTruncatedHashSize = 8;
+
+HostLogMode g_host_log_mode = HostLogMode::kFull;
+std::string* g_host_log_salt;
+}  // namespace
+
+bool ParseHostLogMode(base::StringPiece str, HostLogMode* mode) {
+  if (str == "full") {
+    *mode = HostLogMode::kFull;
+  } else if (str == "truncated-hash") {
+    *mode = HostLogMode::kTruncatedHash;
+  } else if (str == "off") {
+    *mode = HostLogMode::kOff;
+  } else {
+    return false;
+  }
+  return true;
+}
+
+const char* HostLogModeToString(HostLogMode mode) {
+  switch (mode) {
+    case HostLogMode::kFull:
+      return "full";
+    case HostLogMode::kTruncatedHash:
+      return "truncated-hash";
+    case HostLogMode::kOff:
+      return "off";
+  }
+  NOTREACHED();
+  return "";
+}
+
+void SetHostLogMode(HostLogMode mode) {
+  g_host_log_mode = mode;
+  if (mode == HostLogMode::kTruncatedHash && !g_host_log_salt) {
+    g_host_log_salt = new std::string(base::RandBytesAsString(kSaltSize));
+  }
+}
+
+std::string HostForLog(base::StringPiece host) {
+  switch (g_host_log_mode) {
+    case HostLogMode::kFull:
+      return std::string(host);
+    case HostLogMode::kTruncatedHash: {
+      DCHECK(g_host_log_salt);
+      std::string digest = crypto::SHA256HashString(
+          base::StrCat({*g_host_log_salt, base::ToLowerASCII(host)}));
+      return base::StrCat(
+          {"#", base::ToLowerASCII(
+                    base::HexEncode(digest.data(), kTruncatedHashSize))});
+    }
+    case HostLogMode::kOff:
+      return "<redacted>";
+  }
+  NOTREACHED();
+  return std::string();
+}
+
+std::string HostPortForLog(const HostPortPair& host_port) {
+  if (g_host_log_mode == HostLogMode::kFull)
+    return host_port.ToString();
+  return base::StrCat({HostForLog(host_port.host()), ":",
+                       base::NumberToString(host_port.port())});
+}
+
+}  // namespace net
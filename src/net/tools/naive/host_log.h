@@ -0,0 +1,34 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#ifndef NET_TOOLS_NAIVE_HOST_LOG_H_
+#define NET_TOOLS_NAIVE_HOST_LOG_H_
+
+#include <string>
+
+#include "base/strings/string_piece.h"
+
+namespace net {
+
+class HostPortPair;
+
+// Controls how destination hostnames appear in logs.
+enum class HostLogMode {
+  kFull,
+  kTruncatedHash,
+  kOff,
+};
+
+bool ParseHostLogMode(base::StringPiece str, HostLogMode* mode);
+const char* HostLogModeToString(HostLogMode mode);
+
+// Sets the process-wide mode. Must be called before any connection is made.
+void SetHostLogMode(HostLogMode mode);
+
+// Returns |host| rendered according to the current mode. Truncated hashes are
+// salted per process, so they correlate within one run but not across runs.
+std::string HostForLog(base::StringPiece host);
+std::string HostPortForLog(const HostPortPair& host_port);
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_HOST_LOG_H_
@@ -8,10 +8,13 @@
 #include <cstring>
 #include <utility>
 
+#include "base/base64.h"
 #include "base/bind.h"
 #include "base/callback_helpers.h"
 #include "base/logging.h"
 #include "base/rand_util.h"
+#include "base/strings/strcat.h"
+#include "base/strings/string_util.h"
 #include "base/sys_byteorder.h"
 #include "net/base/ip_address.h"
 #include "net/base/net_errors.h"
@@ -19,6 +22,7 @@
 #include "net/log/net_log.h"
 #include "net/third_party/quiche/src/spdy/core/hpack/hpack_constants.h"
 #include "net/tools/naive/naive_proxy_delegate.h"
+#include "net/tools/naive/reverse_tunnel.h"
 
 namespace net {
 
@@ -27,6 +31,15 @@ constexpr int kBufferSize = 64 * 1024;
 constexpr size_t kMaxHeaderSize = 64 * 1024;
 constexpr char kResponseHeader[] = "HTTP/1.1 200 OK\r\nPadding: ";
 constexpr int kResponseHeaderSize = sizeof(kResponseHeader) - 1;
+constexpr char kCompressionHeader[] = "Compression: deflate\r\n";
+constexpr int kCompressionHeaderSize = sizeof(kCompressionHeader) - 1;
+constexpr char kAuthRequiredResponse[] =
+    "HTTP/1.1 407 Proxy Authentication Required\r\n"
+    "Proxy-Authenticate: Basic realm=\"naive\"\r\n"
+    "Content-Length: 0\r\n\r\n";
+constexpr int kAuthRequiredResponseSize = sizeof(kAuthRequiredResponse) - 1;
+constexpr char kBasicAuthPrefix[] = "basic ";
+constexpr size_t kBasicAuthPrefixSize = sizeof(kBasicAuthPrefix) - 1;
 // A plain 200 is 10 bytes. Expected 48 bytes. "Padding" uses up 7 bytes.
 constexpr int kMinPaddingSize = 30;
 constexpr int kMaxPaddingSize = kMinPaddingSize + 32;
@@ -34,12 +47,23 @@ constexpr int kMaxPaddingSize = kMinPaddingSize + 32;
 
 HttpProxySocket::HttpProxySocket(
     std::unique_ptr<StreamSocket> transport_socket,
+    const std::string& user,
+    const std::string& pass,
     ClientPaddingDetectorDelegate* padding_detector_delegate,
+    bool allow_compression,
+    ReverseListener* reverse_listener,
     const NetworkTrafficAnnotationTag& traffic_annotation)
     : io_callback_(base::BindRepeating(&HttpProxySocket::OnIOComplete,
                                        base::Unretained(this))),
       transport_(std::move(transport_socket)),
+      user_(user),
+      pass_(pass),
+      auth_rejected_(false),
       padding_detector_delegate_(padding_detector_delegate),
+      allow_compression_(allow_compression),
+      compression_negotiated_(false),
+      reverse_listener_(reverse_listener),
+      watching_client_(false),
       next_state_(STATE_NONE),
       completed_handshake_(false),
       was_ever_used_(false),
@@ -55,6 +79,10 @@ const HostPortPair& HttpProxySocket::request_endpoint() const {
   return request_endpoint_;
 }
 
+std::unique_ptr<StreamSocket> HttpProxySocket::TakeReverseSocket() {
+  return std::move(reverse_socket_);
+}
+
 int HttpProxySocket::Connect(CompletionOnceCallback callback) {
   DCHECK(transport_);
   DCHECK_EQ(STATE_NONE, next_state_);
@@ -76,6 +104,9 @@ int HttpProxySocket::Connect(CompletionOnceCallback callback) {
 
 void HttpProxySocket::Disconnect() {
   completed_handshake_ = false;
+  if (next_state_ == STATE_REVERSE_ACCEPT_COMPLETE)
+    reverse_listener_->CancelAccept(&reverse_socket_);
+  watching_client_ = false;
   transport_->Disconnect();
 
   // Reset other states to make sure they aren't mistakenly used later.
@@ -240,6 +271,13 @@ int HttpProxySocket::DoLoop(int last_io_result) {
       case STATE_HEADER_READ_COMPLETE:
         rv = DoHeaderReadComplete(rv);
         break;
+      case STATE_REVERSE_ACCEPT:
+        DCHECK_EQ(OK, rv);
+        rv = DoReverseAccept();
+        break;
+      case STATE_REVERSE_ACCEPT_COMPLETE:
+        rv = DoReverseAcceptComplete(rv);
+        break;
       case STATE_HEADER_WRITE:
         DCHECK_EQ(OK, rv);
         rv = DoHeaderWrite();
@@ -312,25 +350,138 @@ int HttpProxySocket::DoHeaderReadComplete(int result) {
     padding_detector_delegate_->SetClientPaddingSupport(
         PaddingSupport::kIncapable);
   }
+  std::string compression;
+  compression_negotiated_ = allow_compression_ &&
+                            headers.GetHeader("compression", &compression) &&
+                            compression == "deflate";
 
   buffer_ = buffer_.substr(header_end + 4);
 
+  if (!user_.empty() || !pass_.empty()) {
+    std::string authorization;
+    if (!headers.GetHeader("proxy-authorization", &authorization) ||
+        !IsAuthorized(authorization)) {
+      auth_rejected_ = true;
+      next_state_ = STATE_HEADER_WRITE;
+      return OK;
+    }
+  }
+
+  if (reverse_listener_ && request_endpoint_.host() == kReverseTunnelHost) {
+    // The client sends nothing before the reply.
+    if (!buffer_.empty())
+      return ERR_INVALID_ARGUMENT;
+    next_state_ = STATE_REVERSE_ACCEPT;
+    return OK;
+  }
+
+  next_state_ = STATE_HEADER_WRITE;
+  return OK;
+}
+
+// Accepts only Basic credentials matching the listen URL.
+bool HttpProxySocket::IsAuthorized(const std::string& authorization) const {
+  if (!base::StartsWith(authorization, kBasicAuthPrefix,
+                        base::CompareCase::INSENSITIVE_ASCII)) {
+    return false;
+  }
+  std::string credentials;
+  if (!base::Base64Decode(
+          base::TrimWhitespaceASCII(
+              base::StringPiece(authorization).substr(kBasicAuthPrefixSize),
+              base::TRIM_ALL),
+          &credentials)) {
+    return false;
+  }
+  return credentials == base::StrCat({user_, ":", pass_});
+}
+
+int HttpProxySocket::DoReverseAccept() {
+  next_state_ = STATE_REVERSE_ACCEPT_COMPLETE;
+
+  int rv = reverse_listener_->Accept(request_endpoint_.port(),
+                                     &reverse_socket_, io_callback_);
+  if (rv != ERR_IO_PENDING)
+    return rv;
+
+  rv = WatchClient();
+  if (rv != ERR_IO_PENDING)
+    reverse_listener_->CancelAccept(&reverse_socket_);
+  return rv;
+}
+
+int HttpProxySocket::DoReverseAcceptComplete(int result) {
+  if (watching_client_) {
+    watching_client_ = false;
+    transport_->CancelReadIfReady();
+  }
+  if (result < 0)
+    return result;
+
   next_state_ = STATE_HEADER_WRITE;
   return OK;
 }
 
+// Notices the client going away while waiting, e.g. when its tunnel request
+// times out, so that the accepted connection is not handed to a dead tunnel.
+int HttpProxySocket::WatchClient() {
+  watch_buf_ = base::MakeRefCounted<IOBuffer>(1);
+  int rv = transport_->ReadIfReady(
+      watch_buf_.get(), 1,
+      base::BindOnce(&HttpProxySocket::OnClientReadable,
+                     base::Unretained(this)));
+  if (rv == ERR_IO_PENDING) {
+    watching_client_ = true;
+    return rv;
+  }
+  // Waits without watching.
+  if (rv == ERR_READ_IF_READY_NOT_IMPLEMENTED)
+    return ERR_IO_PENDING;
+  if (rv > 0)
+    return ERR_INVALID_ARGUMENT;
+  return rv == 0 ? ERR_CONNECTION_CLOSED : rv;
+}
+
+void HttpProxySocket::OnClientReadable(int result) {
+  DCHECK_EQ(STATE_REVERSE_ACCEPT_COMPLETE, next_state_);
+  watching_client_ = false;
+  if (result == OK) {
+    result = WatchClient();
+    if (result == ERR_IO_PENDING)
+      return;
+  }
+  reverse_listener_->CancelAccept(&reverse_socket_);
+  OnIOComplete(result);
+}
+
 int HttpProxySocket::DoHeaderWrite() {
   next_state_ = STATE_HEADER_WRITE_COMPLETE;
 
+  if (auth_rejected_) {
+    header_write_size_ = kAuthRequiredResponseSize;
+    handshake_buf_ = base::MakeRefCounted<IOBuffer>(header_write_size_);
+    std::memcpy(handshake_buf_->data(), kAuthRequiredResponse,
+                header_write_size_);
+    return transport_->Write(handshake_buf_.get(), header_write_size_,
+                             io_callback_, traffic_annotation_);
+  }
+
   // Adds padding.
   int padding_size = base::RandInt(kMinPaddingSize, kMaxPaddingSize);
-  header_write_size_ = kResponseHeaderSize + padding_size + 4;
+  int compression_size = compression_negotiated_ ? kCompressionHeaderSize : 0;
+  header_write_size_ =
+      kResponseHeaderSize + padding_size + 2 + compression_size + 2;
   handshake_buf_ = base::MakeRefCounted<IOBuffer>(header_write_size_);
   char* p = handshake_buf_->data();
   std::memcpy(p, kResponseHeader, kResponseHeaderSize);
-  FillNonindexHeaderValue(base::RandUint64(), p + kResponseHeaderSize,
-                          padding_size);
-  std::memcpy(p + kResponseHeaderSize + padding_size, "\r\n\r\n", 4);
+  p += kResponseHeaderSize;
+  FillNonindexHeaderValue(base::RandUint64(), p, padding_size);
+  p += padding_size;
+  std::memcpy(p, "\r\n", 2);
+  p += 2;
+  std::memcpy(p, kCompressionHeader, compression_size);
+  p += compression_size;
+  std::memcpy(p, "\r\n", 2);
 
   return transport_->Write(handshake_buf_.get(), header_write_size_,
                            io_callback_, traffic_annotation_);
@@ -344,6 +495,9 @@ int HttpProxySocket::DoHeaderWriteComplete(int result) {
     return ERR_FAILED;
   }
 
+  if (auth_rejected_)
+    return ERR_PROXY_AUTH_REQUESTED;
+
   completed_handshake_ = true;
   next_state_ = STATE_NONE;
   return OK;
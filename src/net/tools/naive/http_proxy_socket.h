@@ -27,12 +27,19 @@
 namespace net {
 struct NetworkTrafficAnnotationTag;
 class ClientPaddingDetectorDelegate;
+class ReverseListener;
 
 // This StreamSocket is used to setup a HTTP CONNECT tunnel.
 class HttpProxySocket : public StreamSocket {
  public:
+  // Requires Basic proxy authorization unless |user| and |pass| are empty.
+  // |reverse_listener| is null if reverse tunnels are not allowed.
   HttpProxySocket(std::unique_ptr<StreamSocket> transport_socket,
+                  const std::string& user,
+                  const std::string& pass,
                   ClientPaddingDetectorDelegate* padding_detector_delegate,
+                  bool allow_compression,
+                  ReverseListener* reverse_listener,
                   const NetworkTrafficAnnotationTag& traffic_annotation);
 
   // On destruction Disconnect() is called.
@@ -40,6 +47,14 @@ class HttpProxySocket : public StreamSocket {
 
   const HostPortPair& request_endpoint() const;
 
+  // Whether the client asked for compression and it is allowed. Valid after
+  // Connect() succeeds.
+  bool compression_negotiated() const { return compression_negotiated_; }
+
+  // Returns the connection accepted for a reverse tunnel, or null if this is
+  // not one. Valid after Connect() succeeds.
+  std::unique_ptr<StreamSocket> TakeReverseSocket();
+
   // StreamSocket implementation.
 
   int Connect(CompletionOnceCallback callback) override;
@@ -76,6 +91,8 @@ class HttpProxySocket : public StreamSocket {
   enum State {
     STATE_HEADER_READ,
     STATE_HEADER_READ_COMPLETE,
+    STATE_REVERSE_ACCEPT,
+    STATE_REVERSE_ACCEPT_COMPLETE,
     STATE_HEADER_WRITE,
     STATE_HEADER_WRITE_COMPLETE,
     STATE_NONE,
@@ -90,12 +107,29 @@ class HttpProxySocket : public StreamSocket {
   int DoHeaderWriteComplete(int result);
   int DoHeaderRead();
   int DoHeaderReadComplete(int result);
+  bool IsAuthorized(const std::string& authorization) const;
+  int DoReverseAccept();
+  int DoReverseAcceptComplete(int result);
+  int WatchClient();
+  void OnClientReadable(int result);
 
   CompletionRepeatingCallback io_callback_;
 
   // Stores the underlying socket.
   std::unique_ptr<StreamSocket> transport_;
+  std::string user_;
+  std::string pass_;
+  // Answers with 407 and fails Connect().
+  bool auth_rejected_;
   ClientPaddingDetectorDelegate* padding_detector_delegate_;
+  bool allow_compression_;
+  bool compression_negotiated_;
+  ReverseListener* reverse_listener_;
+  std::unique_ptr<StreamSocket> reverse_socket_;
+  // Whether the client is watched for going away while waiting for a
+  // reverse tunnel connection.
+  bool watching_client_;
+  scoped_refptr<IOBuffer> watch_buf_;
 
   State next_state_;
 
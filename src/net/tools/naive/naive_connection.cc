@@ -18,23 +18,34 @@
 #include "net/base/load_flags.h"
 #include "net/base/net_errors.h"
 #include "net/base/privacy_mode.h"
+#include "net/http/http_network_session.h"
+#include "net/http/http_response_headers.h"
+#include "net/http/http_response_info.h"
+#include "net/http/proxy_client_socket.h"
 #include "net/proxy_resolution/proxy_info.h"
 #include "net/socket/client_socket_handle.h"
 #include "net/socket/client_socket_pool_manager.h"
 #include "net/socket/stream_socket.h"
 #include "net/spdy/spdy_session.h"
+#include "net/tools/naive/buffer_budget.h"
+#include "net/tools/naive/compressed_socket.h"
+#include "net/tools/naive/host_log.h"
 #include "net/tools/naive/http_proxy_socket.h"
 #include "net/tools/naive/redirect_resolver.h"
 #include "net/tools/naive/socks5_server_socket.h"
+#include "net/tools/naive/upload_scheduler.h"
 
 #if defined(OS_LINUX)
 #include <linux/netfilter_ipv4.h>
+#include <linux/netfilter_ipv6/ip6_tables.h>
 #include <netinet/in.h>
 #include <sys/socket.h>
 
+#include "net/base/ip_address.h"
 #include "net/base/ip_endpoint.h"
 #include "net/base/sockaddr_storage.h"
 #include "net/socket/tcp_client_socket.h"
+#include "net/tools/naive/nat64_detector.h"
 #endif
 
 namespace net {
@@ -44,6 +55,17 @@ constexpr int kBufferSize = 64 * 1024;
 constexpr int kFirstPaddings = 8;
 constexpr int kPaddingHeaderSize = 3;
 constexpr int kMaxPaddingSize = 255;
+// A scheduled write pending for this long is taken as blocked by flow
+// control or a full send buffer.
+constexpr int kBlockedWriteMilliseconds = 50;
+
+bool IsCompressionAccepted(const HttpResponseInfo* response) {
+  std::string compression;
+  return response && response->headers &&
+         response->headers->GetNormalizedHeader("compression",
+                                                &compression) &&
+         compression == "deflate";
+}
 }  // namespace
 
 NaiveConnection::NaiveConnection(
@@ -54,10 +76,14 @@ NaiveConnection::NaiveConnection(
     const SSLConfig& server_ssl_config,
     const SSLConfig& proxy_ssl_config,
     RedirectResolver* resolver,
+    Nat64Detector* nat64_detector,
     HttpNetworkSession* session,
     const NetworkIsolationKey& network_isolation_key,
+    UploadScheduler* upload_scheduler,
+    BufferBudget* buffer_budget,
     const NetLogWithSource& net_log,
     std::unique_ptr<StreamSocket> accepted_socket,
+    StreamSocket* transport_socket,
     const NetworkTrafficAnnotationTag& traffic_annotation)
     : id_(id),
       protocol_(protocol),
@@ -66,15 +92,21 @@ NaiveConnection::NaiveConnection(
       server_ssl_config_(server_ssl_config),
       proxy_ssl_config_(proxy_ssl_config),
       resolver_(resolver),
+      nat64_detector_(nat64_detector),
       session_(session),
       network_isolation_key_(network_isolation_key),
+      upload_scheduler_(upload_scheduler),
+      buffer_budget_(buffer_budget),
       net_log_(net_log),
       next_state_(STATE_NONE),
       client_socket_(std::move(accepted_socket)),
+      transport_socket_(transport_socket),
       server_socket_handle_(std::make_unique<ClientSocketHandle>()),
       sockets_{client_socket_.get(), nullptr},
       errors_{OK, OK},
       write_pending_{false, false},
+      buffer_reserved_{0, 0},
+      scheduled_write_size_(0),
       early_pull_pending_(false),
       can_push_to_server_(false),
       early_pull_result_(ERR_IO_PENDING),
@@ -88,18 +120,50 @@ NaiveConnection::NaiveConnection(
 }
 
 NaiveConnection::~NaiveConnection() {
+  for (const auto& socket : compressed_sockets_) {
+    if (socket) {
+      int64_t payload_bytes = socket->bytes_written() + socket->bytes_read();
+      int64_t wire_bytes =
+          socket->wire_bytes_written() + socket->wire_bytes_read();
+      int64_t saved_percent =
+          payload_bytes > 0
+              ? (payload_bytes - wire_bytes) * 100 / payload_bytes
+              : 0;
+      LOG(INFO) << "Connection " << id_ << " compressed "
+                << socket->bytes_written() << " to "
+                << socket->wire_bytes_written() << " bytes, decompressed "
+                << socket->wire_bytes_read() << " to " << socket->bytes_read()
+                << " bytes, saved " << saved_percent << "%";
+    }
+  }
+  if (upload_scheduler_ && !upload_scheduler_->CancelRequest(id_))
+    ReleaseScheduledWrite();
+  if (buffer_budget_)
+    buffer_budget_->RemoveTunnel(id_);
   Disconnect();
 }
 
+void NaiveConnection::SetReverseForward(const ReverseForward& forward) {
+  DCHECK_EQ(protocol_, ClientProtocol::kReverse);
+  reverse_forward_ = forward;
+}
+
 int NaiveConnection::Connect(CompletionOnceCallback callback) {
-  DCHECK(client_socket_);
+  DCHECK(client_socket_ || protocol_ == ClientProtocol::kReverse);
   DCHECK_EQ(next_state_, STATE_NONE);
   DCHECK(!connect_callback_);
 
   if (full_duplex_)
     return OK;
 
-  next_state_ = STATE_CONNECT_CLIENT;
+  if (protocol_ == ClientProtocol::kReverse) {
+    // The target is connected only after the server side, so there is no
+    // early pull.
+    early_pull_result_ = 0;
+    next_state_ = STATE_CONNECT_SERVER;
+  } else {
+    next_state_ = STATE_CONNECT_CLIENT;
+  }
 
   int rv = DoLoop(OK);
   if (rv == ERR_IO_PENDING) {
@@ -113,7 +177,10 @@ void NaiveConnection::Disconnect() {
   // Closes server side first because latency is higher.
   if (server_socket_handle_->socket())
     server_socket_handle_->socket()->Disconnect();
-  client_socket_->Disconnect();
+  if (reverse_socket_)
+    reverse_socket_->Disconnect();
+  if (client_socket_)
+    client_socket_->Disconnect();
 
   next_state_ = STATE_NONE;
   connect_callback_.Reset();
@@ -158,6 +225,13 @@ int NaiveConnection::DoLoop(int last_io_result) {
       case STATE_CONNECT_SERVER_COMPLETE:
         rv = DoConnectServerComplete(rv);
         break;
+      case STATE_CONNECT_TARGET:
+        DCHECK_EQ(rv, OK);
+        rv = DoConnectTarget();
+        break;
+      case STATE_CONNECT_TARGET_COMPLETE:
+        rv = DoConnectTargetComplete(rv);
+        break;
       default:
         NOTREACHED() << "bad state";
         rv = ERR_UNEXPECTED;
@@ -177,6 +251,14 @@ int NaiveConnection::DoConnectClientComplete(int result) {
   if (result < 0)
     return result;
 
+  if (protocol_ == ClientProtocol::kHttp &&
+      static_cast<const HttpProxySocket*>(client_socket_.get())
+          ->compression_negotiated()) {
+    compressed_sockets_[kClient] = std::make_unique<CompressedSocket>(
+        client_socket_.get(), traffic_annotation_);
+    sockets_[kClient] = compressed_sockets_[kClient].get();
+  }
+
   // For proxy client sockets, padding support detection is finished after the
   // first server response which means there will be one missed early pull. For
   // proxy server sockets (HttpProxySocket), padding support detection is
@@ -210,26 +292,45 @@ int NaiveConnection::DoConnectServer() {
         static_cast<const Socks5ServerSocket*>(client_socket_.get());
     origin = socket->request_endpoint();
   } else if (protocol_ == ClientProtocol::kHttp) {
-    const auto* socket =
-        static_cast<const HttpProxySocket*>(client_socket_.get());
+    auto* socket = static_cast<HttpProxySocket*>(client_socket_.get());
+    reverse_socket_ = socket->TakeReverseSocket();
+    if (reverse_socket_) {
+      LOG(INFO) << "Connection " << id_ << " reverse from port "
+                << socket->request_endpoint().port();
+      return OK;
+    }
     origin = socket->request_endpoint();
+  } else if (protocol_ == ClientProtocol::kReverse) {
+    origin = HostPortPair(kReverseTunnelHost, reverse_forward_.remote_port);
   } else if (protocol_ == ClientProtocol::kRedir) {
 #if defined(OS_LINUX)
-    const auto* socket =
-        static_cast<const TCPClientSocket*>(client_socket_.get());
+    const auto* socket = static_cast<const TCPClientSocket*>(transport_socket_);
     int sd = socket->SocketDescriptorForTesting();
+    IPEndPoint local_ipe;
+    bool has_local_ipe = socket->GetLocalAddress(&local_ipe) == OK;
     SockaddrStorage dst;
     int rv;
-    rv = getsockopt(sd, SOL_IP, SO_ORIGINAL_DST, dst.addr, &dst.addr_len);
+    if (has_local_ipe && local_ipe.address().IsIPv6() &&
+        !local_ipe.address().IsIPv4MappedIPv6()) {
+      rv = getsockopt(sd, SOL_IPV6, IP6T_SO_ORIGINAL_DST, dst.addr,
+                      &dst.addr_len);
+    } else {
+      rv = getsockopt(sd, SOL_IP, SO_ORIGINAL_DST, dst.addr, &dst.addr_len);
+    }
     if (rv == 0) {
       IPEndPoint ipe;
       if (ipe.FromSockAddr(dst.addr, dst.addr_len)) {
-        const auto& addr = ipe.address();
+        IPAddress addr = ipe.address();
+        // Maps addresses synthesized by DNS64 back to the IPv4 address, which
+        // may have come from the redirect resolver.
+        IPAddress ipv4;
+        if (nat64_detector_ && nat64_detector_->ExtractIPv4(addr, &ipv4))
+          addr = ipv4;
         auto name = resolver_->FindNameByAddress(addr);
         if (!name.empty()) {
           origin = HostPortPair(name, ipe.port());
         } else if (!resolver_->IsInResolvedRange(addr)) {
-          origin = HostPortPair::FromIPEndPoint(ipe);
+          origin = HostPortPair::FromIPEndPoint(IPEndPoint(addr, ipe.port()));
         } else {
           LOG(ERROR) << "Connection " << id_ << " to unresolved name for "
                      << addr.ToString();
@@ -239,6 +340,8 @@ int NaiveConnection::DoConnectServer() {
     }
 #else
     static_cast<void>(resolver_);
+    static_cast<void>(nat64_detector_);
+    static_cast<void>(transport_socket_);
 #endif
   }
 
@@ -247,7 +350,7 @@ int NaiveConnection::DoConnectServer() {
     return ERR_ADDRESS_INVALID;
   }
 
-  LOG(INFO) << "Connection " << id_ << " to " << origin.ToString();
+  LOG(INFO) << "Connection " << id_ << " to " << HostPortForLog(origin);
 
   // Ignores socket limit set by socket pool for this type of socket.
   return InitSocketHandleForRawConnect2(
@@ -261,8 +364,56 @@ int NaiveConnection::DoConnectServerComplete(int result) {
   if (result < 0)
     return result;
 
-  DCHECK(server_socket_handle_->socket());
-  sockets_[kServer] = server_socket_handle_->socket();
+  if (reverse_socket_) {
+    sockets_[kServer] = reverse_socket_.get();
+  } else {
+    DCHECK(server_socket_handle_->socket());
+    sockets_[kServer] = server_socket_handle_->socket();
+  }
+
+  // Decided by this tunnel's own response, which Connect() waits for when
+  // compression is requested.
+  if (!reverse_socket_ && proxy_info_.proxy_server().is_http_like() &&
+      IsCompressionAccepted(static_cast<ProxyClientSocket*>(sockets_[kServer])
+                                ->GetConnectResponseInfo())) {
+    compressed_sockets_[kServer] = std::make_unique<CompressedSocket>(
+        sockets_[kServer], traffic_annotation_);
+    sockets_[kServer] = compressed_sockets_[kServer].get();
+  }
+
+  if (protocol_ == ClientProtocol::kReverse) {
+    next_state_ = STATE_CONNECT_TARGET;
+    return OK;
+  }
+
+  full_duplex_ = true;
+  next_state_ = STATE_NONE;
+  return OK;
+}
+
+int NaiveConnection::DoConnectTarget() {
+  next_state_ = STATE_CONNECT_TARGET_COMPLETE;
+
+  LOG(INFO) << "Connection " << id_ << " reverse to "
+            << HostPortForLog(reverse_forward_.target);
+
+  target_proxy_info_.UseDirect();
+  target_proxy_info_.set_traffic_annotation(
+      MutableNetworkTrafficAnnotationTag(traffic_annotation_));
+  target_socket_handle_ = std::make_unique<ClientSocketHandle>();
+  return InitSocketHandleForRawConnect2(
+      reverse_forward_.target, session_, LOAD_IGNORE_LIMITS, MAXIMUM_PRIORITY,
+      target_proxy_info_, server_ssl_config_, proxy_ssl_config_,
+      PRIVACY_MODE_DISABLED, network_isolation_key_, net_log_,
+      target_socket_handle_.get(), io_callback_);
+}
+
+int NaiveConnection::DoConnectTargetComplete(int result) {
+  if (result < 0)
+    return result;
+
+  client_socket_ = target_socket_handle_->PassSocket();
+  sockets_[kClient] = client_socket_.get();
 
   full_duplex_ = true;
   next_state_ = STATE_NONE;
@@ -334,6 +485,18 @@ void NaiveConnection::Pull(Direction from, Direction to) {
 }
 
 void NaiveConnection::Push(Direction from, Direction to, int size) {
+  // Holds the data read until there is memory for it, which also stops
+  // reading from this side.
+  if (buffer_budget_ && buffer_reserved_[from] == 0) {
+    if (!buffer_budget_->Reserve(
+            id_, size,
+            base::BindOnce(&NaiveConnection::OnBufferReserved,
+                           weak_ptr_factory_.GetWeakPtr(), from, to, size))) {
+      return;
+    }
+    buffer_reserved_[from] = size;
+  }
+
   int write_size = size;
   int write_offset = 0;
   auto padding_direction = padding_detector_delegate_->GetPaddingDirection();
@@ -433,15 +596,66 @@ void NaiveConnection::Push(Direction from, Direction to, int size) {
     write_buffers_[to]->DidConsume(write_offset);
   }
   write_pending_[to] = true;
+  if (to == kServer && upload_scheduler_) {
+    scheduled_write_size_ = write_size;
+    if (!upload_scheduler_->RequestWrite(
+            id_, write_size,
+            base::BindOnce(&NaiveConnection::OnPushScheduled,
+                           weak_ptr_factory_.GetWeakPtr(), from, to))) {
+      return;
+    }
+  }
+  DoPushWrite(from, to);
+}
+
+void NaiveConnection::DoPushWrite(Direction from, Direction to) {
   DCHECK(sockets_[to]);
   int rv = sockets_[to]->Write(
-      write_buffers_[to].get(), write_size,
+      write_buffers_[to].get(), write_buffers_[to]->BytesRemaining(),
       base::BindRepeating(&NaiveConnection::OnPushComplete,
                           weak_ptr_factory_.GetWeakPtr(), from, to),
       traffic_annotation_);
 
-  if (rv != ERR_IO_PENDING)
+  if (rv != ERR_IO_PENDING) {
     OnPushComplete(from, to, rv);
+    return;
+  }
+  // A blocked write returns its grant early so that it doesn't hold up the
+  // uploads of other tunnels.
+  if (scheduled_write_size_ > 0) {
+    blocked_write_timer_.Start(
+        FROM_HERE,
+        base::TimeDelta::FromMilliseconds(kBlockedWriteMilliseconds),
+        base::BindOnce(&NaiveConnection::ReleaseScheduledWrite,
+                       base::Unretained(this)));
+  }
+}
+
+void NaiveConnection::OnPushScheduled(Direction from, Direction to) {
+  // Disconnected while waiting for the grant.
+  if (!IsConnected(to)) {
+    ReleaseScheduledWrite();
+    return;
+  }
+  DoPushWrite(from, to);
+}
+
+void NaiveConnection::OnBufferReserved(Direction from,
+                                       Direction to,
+                                       int size) {
+  buffer_reserved_[from] = size;
+  // The bytes are returned when the connection is removed.
+  if (!IsConnected(to))
+    return;
+  Push(from, to, size);
+}
+
+void NaiveConnection::ReleaseScheduledWrite() {
+  blocked_write_timer_.Stop();
+  if (scheduled_write_size_ == 0)
+    return;
+  upload_scheduler_->OnWriteComplete(scheduled_write_size_);
+  scheduled_write_size_ = 0;
 }
 
 void NaiveConnection::Disconnect(Direction side) {
@@ -531,6 +745,13 @@ void NaiveConnection::OnPushComplete(Direction from, Direction to, int result) {
   }
 
   write_pending_[to] = false;
+  if (to == kServer)
+    ReleaseScheduledWrite();
+  if (buffer_reserved_[from] > 0) {
+    write_buffers_[to] = nullptr;
+    buffer_budget_->Release(id_, buffer_reserved_[from]);
+    buffer_reserved_[from] = 0;
+  }
   // Checks for termination even if result is OK.
   OnPushError(from, to, result >= 0 ? OK : result);
 
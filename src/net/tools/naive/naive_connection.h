@@ -13,24 +13,30 @@
 #include "base/memory/scoped_refptr.h"
 #include "base/memory/weak_ptr.h"
 #include "base/time/time.h"
+#include "base/timer/timer.h"
 #include "net/base/completion_once_callback.h"
 #include "net/base/completion_repeating_callback.h"
+#include "net/proxy_resolution/proxy_info.h"
 #include "net/tools/naive/naive_protocol.h"
 #include "net/tools/naive/naive_proxy_delegate.h"
+#include "net/tools/naive/reverse_tunnel.h"
 
 namespace net {
 
+class BufferBudget;
 class ClientSocketHandle;
+class CompressedSocket;
 class DrainableIOBuffer;
 class HttpNetworkSession;
 class IOBuffer;
 class NetLogWithSource;
-class ProxyInfo;
 class StreamSocket;
 struct NetworkTrafficAnnotationTag;
 struct SSLConfig;
 class RedirectResolver;
+class Nat64Detector;
 class NetworkIsolationKey;
+class UploadScheduler;
 
 class NaiveConnection {
  public:
@@ -44,14 +50,25 @@ class NaiveConnection {
       const SSLConfig& server_ssl_config,
       const SSLConfig& proxy_ssl_config,
       RedirectResolver* resolver,
+      Nat64Detector* nat64_detector,
       HttpNetworkSession* session,
       const NetworkIsolationKey& network_isolation_key,
+      UploadScheduler* upload_scheduler,
+      BufferBudget* buffer_budget,
       const NetLogWithSource& net_log,
       std::unique_ptr<StreamSocket> accepted_socket,
+      StreamSocket* transport_socket,
       const NetworkTrafficAnnotationTag& traffic_annotation);
   ~NaiveConnection();
 
   unsigned int id() const { return id_; }
+
+  // Makes this the client side of a reverse tunnel for |forward|, without an
+  // accepted socket. The server side waits for the proxy server to accept a
+  // connection to the remote port, then the client side connects to the
+  // target. Must be called before Connect().
+  void SetReverseForward(const ReverseForward& forward);
+
   int Connect(CompletionOnceCallback callback);
   void Disconnect();
   int Run(CompletionOnceCallback callback);
@@ -62,6 +79,8 @@ class NaiveConnection {
     STATE_CONNECT_CLIENT_COMPLETE,
     STATE_CONNECT_SERVER,
     STATE_CONNECT_SERVER_COMPLETE,
+    STATE_CONNECT_TARGET,
+    STATE_CONNECT_TARGET_COMPLETE,
     STATE_NONE,
   };
 
@@ -80,8 +99,14 @@ class NaiveConnection {
   int DoConnectClientComplete(int result);
   int DoConnectServer();
   int DoConnectServerComplete(int result);
+  int DoConnectTarget();
+  int DoConnectTargetComplete(int result);
   void Pull(Direction from, Direction to);
   void Push(Direction from, Direction to, int size);
+  void DoPushWrite(Direction from, Direction to);
+  void OnPushScheduled(Direction from, Direction to);
+  void OnBufferReserved(Direction from, Direction to, int size);
+  void ReleaseScheduledWrite();
   void Disconnect(Direction side);
   bool IsConnected(Direction side);
   void OnBothDisconnected();
@@ -97,8 +122,14 @@ class NaiveConnection {
   const SSLConfig& server_ssl_config_;
   const SSLConfig& proxy_ssl_config_;
   RedirectResolver* resolver_;
+  // Null if not in redir mode.
+  Nat64Detector* nat64_detector_;
   HttpNetworkSession* session_;
   const NetworkIsolationKey& network_isolation_key_;
+  // Null if the upload path is not shared with other tunnels.
+  UploadScheduler* upload_scheduler_;
+  // Null if buffer memory is not limited.
+  BufferBudget* buffer_budget_;
   const NetLogWithSource& net_log_;
 
   CompletionRepeatingCallback io_callback_;
@@ -108,13 +139,30 @@ class NaiveConnection {
   State next_state_;
 
   std::unique_ptr<StreamSocket> client_socket_;
+  // The accepted TCP socket underneath |client_socket_|.
+  StreamSocket* transport_socket_;
   std::unique_ptr<ClientSocketHandle> server_socket_handle_;
+  // The connection accepted for a reverse tunnel, used as the server side.
+  std::unique_ptr<StreamSocket> reverse_socket_;
+  // Set for the client side of a reverse tunnel.
+  ReverseForward reverse_forward_;
+  ProxyInfo target_proxy_info_;
+  std::unique_ptr<ClientSocketHandle> target_socket_handle_;
+  // Wraps the sides facing another naive that agreed to compression.
+  std::unique_ptr<CompressedSocket> compressed_sockets_[kNumDirections];
 
   StreamSocket* sockets_[kNumDirections];
   scoped_refptr<IOBuffer> read_buffers_[kNumDirections];
   scoped_refptr<DrainableIOBuffer> write_buffers_[kNumDirections];
   int errors_[kNumDirections];
   bool write_pending_[kNumDirections];
+  // Bytes read from this side and reserved from |buffer_budget_| until they
+  // are written, or 0.
+  int buffer_reserved_[kNumDirections];
+  // Size of the write to the server granted by |upload_scheduler_|, or 0
+  // once it is returned.
+  int scheduled_write_size_;
+  base::OneShotTimer blocked_write_timer_;
   int bytes_passed_without_yielding_[kNumDirections];
   base::TimeTicks yield_after_time_[kNumDirections];
 
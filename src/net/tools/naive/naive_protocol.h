@@ -9,6 +9,11 @@ enum class ClientProtocol {
   kSocks5,
   kHttp,
   kRedir,
+  // Detects one of the above from the first bytes sent by the client.
+  kAuto,
+  // Not listened on. The client side is a connection to the target of a
+  // reverse tunnel.
+  kReverse,
 };
 
 // Adds padding for traffic from this direction.
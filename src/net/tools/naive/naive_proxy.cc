@@ -21,18 +21,30 @@
 #include "net/socket/client_socket_pool_manager.h"
 #include "net/socket/server_socket.h"
 #include "net/socket/stream_socket.h"
+#include "net/tools/naive/host_log.h"
 #include "net/tools/naive/http_proxy_socket.h"
 #include "net/tools/naive/naive_proxy_delegate.h"
+#include "net/tools/naive/protocol_sniffing_socket.h"
 #include "net/tools/naive/socks5_server_socket.h"
 
 namespace net {
 
+namespace {
+// Tunnels kept waiting for each reverse forward, so that a new connection
+// can be handed over while the previous tunnel is being replaced.
+constexpr int kReverseTunnelsPerForward = 2;
+constexpr int kReverseRetryDelaySeconds = 5;
+}  // namespace
+
 NaiveProxy::NaiveProxy(std::unique_ptr<ServerSocket> listen_socket,
                        ClientProtocol protocol,
                        const std::string& listen_user,
                        const std::string& listen_pass,
                        int concurrency,
+                       bool compression,
+                       int64_t buffer_memory,
                        RedirectResolver* resolver,
+                       Nat64Detector* nat64_detector,
                        HttpNetworkSession* session,
                        const NetworkTrafficAnnotationTag& traffic_annotation)
     : listen_socket_(std::move(listen_socket)),
@@ -40,11 +52,14 @@ NaiveProxy::NaiveProxy(std::unique_ptr<ServerSocket> listen_socket,
       listen_user_(listen_user),
       listen_pass_(listen_pass),
       concurrency_(std::min(4, std::max(1, concurrency))),
+      compression_(compression),
       resolver_(resolver),
+      nat64_detector_(nat64_detector),
       session_(session),
       net_log_(
           NetLogWithSource::Make(session->net_log(), NetLogSourceType::NONE)),
       last_id_(0),
+      draining_(false),
       traffic_annotation_(traffic_annotation) {
   const auto& proxy_config = static_cast<ConfiguredProxyResolutionService*>(
                                  session_->proxy_resolution_service())
@@ -60,20 +75,104 @@ NaiveProxy::NaiveProxy(std::unique_ptr<ServerSocket> listen_socket,
   session_->GetSSLConfig(&server_ssl_config_, &proxy_ssl_config_);
   proxy_ssl_config_.disable_cert_verification_network_fetches = true;
 
+  // Tunnels only share an upload path over HTTP/2 and QUIC sessions.
+  bool multiplexed = proxy_info_.proxy_server().is_secure_http_like();
   for (int i = 0; i < concurrency_; i++) {
     network_isolation_keys_.push_back(NetworkIsolationKey::CreateTransient());
+    if (multiplexed)
+      upload_schedulers_.push_back(std::make_unique<UploadScheduler>());
+  }
+
+  if (buffer_memory > 0) {
+    buffer_budget_ = std::make_unique<BufferBudget>(
+        buffer_memory, base::BindRepeating(&NaiveProxy::Evict,
+                                           weak_ptr_factory_.GetWeakPtr()));
   }
 
-  DCHECK(listen_socket_);
   // Start accepting connections in next run loop in case when delegate is not
   // ready to get callbacks.
-  base::ThreadTaskRunnerHandle::Get()->PostTask(
-      FROM_HERE, base::BindOnce(&NaiveProxy::DoAcceptLoop,
-                                weak_ptr_factory_.GetWeakPtr()));
+  if (listen_socket_) {
+    base::ThreadTaskRunnerHandle::Get()->PostTask(
+        FROM_HERE, base::BindOnce(&NaiveProxy::DoAcceptLoop,
+                                  weak_ptr_factory_.GetWeakPtr()));
+  }
 }
 
 NaiveProxy::~NaiveProxy() = default;
 
+void NaiveProxy::AddReverseForward(const ReverseForward& forward) {
+  DCHECK(!proxy_info_.is_direct());
+  LOG(INFO) << "Forwarding port " << forward.remote_port
+            << " of the proxy server to " << HostPortForLog(forward.target);
+  reverse_forwards_.push_back(forward);
+  for (int i = 0; i < kReverseTunnelsPerForward; i++) {
+    base::ThreadTaskRunnerHandle::Get()->PostTask(
+        FROM_HERE,
+        base::BindOnce(&NaiveProxy::DoReverse, weak_ptr_factory_.GetWeakPtr(),
+                       reverse_forwards_.size() - 1));
+  }
+}
+
+void NaiveProxy::AllowReverseTunnels(const IPAddress& bind_address,
+                                     const std::set<uint16_t>& ports) {
+  reverse_listener_ = std::make_unique<ReverseListener>(bind_address, ports,
+                                                        session_->net_log());
+}
+
+void NaiveProxy::KeepSpareSession() {
+  DCHECK(proxy_info_.proxy_server().is_https());
+  LOG(INFO) << "Keeping a spare session to the proxy server";
+  spare_session_ = std::make_unique<SpareSession>(
+      proxy_info_.proxy_server(), proxy_ssl_config_, session_, net_log_);
+}
+
+void NaiveProxy::Drain() {
+  draining_ = true;
+  listen_socket_.reset();
+  spare_session_.reset();
+
+  for (auto& item : sniffing_socket_by_id_) {
+    base::ThreadTaskRunnerHandle::Get()->DeleteSoon(FROM_HERE,
+                                                    std::move(item.second));
+  }
+  sniffing_socket_by_id_.clear();
+
+  // Includes reverse tunnels still waiting for a connection.
+  std::vector<unsigned int> connection_ids;
+  for (const auto& item : connection_by_id_) {
+    if (!running_ids_.count(item.first))
+      connection_ids.push_back(item.first);
+  }
+  for (unsigned int connection_id : connection_ids)
+    Close(connection_id, ERR_ABORTED);
+
+  // Frees the reverse tunnel ports after the waiting tunnels above are
+  // destroyed.
+  if (reverse_listener_) {
+    base::ThreadTaskRunnerHandle::Get()->DeleteSoon(
+        FROM_HERE, std::move(reverse_listener_));
+  }
+}
+
+void NaiveProxy::CloseAll() {
+  // Cancels pending accepts and reverse tunnel retries.
+  weak_ptr_factory_.InvalidateWeakPtrs();
+  listen_socket_.reset();
+  spare_session_.reset();
+
+  for (auto& item : sniffing_socket_by_id_) {
+    base::ThreadTaskRunnerHandle::Get()->DeleteSoon(FROM_HERE,
+                                                    std::move(item.second));
+  }
+  sniffing_socket_by_id_.clear();
+
+  std::vector<unsigned int> connection_ids;
+  for (const auto& item : connection_by_id_)
+    connection_ids.push_back(item.first);
+  for (unsigned int connection_id : connection_ids)
+    Close(connection_id, ERR_ABORTED);
+}
+
 void NaiveProxy::DoAcceptLoop() {
   int result;
   do {
@@ -97,10 +196,64 @@ void NaiveProxy::HandleAcceptResult(int result) {
     LOG(ERROR) << "Accept error: rv=" << result;
     return;
   }
-  DoConnect();
+  if (protocol_ == ClientProtocol::kAuto) {
+    DoDetect();
+    return;
+  }
+  auto* transport_socket = accepted_socket_.get();
+  DoConnect(++last_id_, protocol_, std::move(accepted_socket_),
+            transport_socket);
 }
 
-void NaiveProxy::DoConnect() {
+void NaiveProxy::DoDetect() {
+  last_id_++;
+  auto socket =
+      std::make_unique<ProtocolSniffingSocket>(std::move(accepted_socket_));
+  auto* socket_ptr = socket.get();
+  sniffing_socket_by_id_[last_id_] = std::move(socket);
+  int result = socket_ptr->Connect(
+      base::BindOnce(&NaiveProxy::OnDetectComplete,
+                     weak_ptr_factory_.GetWeakPtr(), last_id_));
+  if (result == ERR_IO_PENDING)
+    return;
+  HandleDetectResult(last_id_, result);
+}
+
+void NaiveProxy::OnDetectComplete(unsigned int connection_id, int result) {
+  HandleDetectResult(connection_id, result);
+}
+
+void NaiveProxy::HandleDetectResult(unsigned int connection_id, int result) {
+  auto it = sniffing_socket_by_id_.find(connection_id);
+  if (it == sniffing_socket_by_id_.end())
+    return;
+  std::unique_ptr<ProtocolSniffingSocket> socket = std::move(it->second);
+  sniffing_socket_by_id_.erase(it);
+
+  // Redirected traffic carries no credentials to check.
+  if (result == OK && socket->protocol() == ClientProtocol::kRedir &&
+      (!listen_user_.empty() || !listen_pass_.empty())) {
+    result = ERR_ACCESS_DENIED;
+  }
+
+  if (result != OK) {
+    LOG(INFO) << "Connection " << connection_id
+              << " closed: " << ErrorToShortString(result);
+    // Called from the socket's own callback, so destroys it later.
+    base::ThreadTaskRunnerHandle::Get()->DeleteSoon(FROM_HERE,
+                                                    std::move(socket));
+    return;
+  }
+
+  ClientProtocol protocol = socket->protocol();
+  auto* transport_socket = socket->transport_socket();
+  DoConnect(connection_id, protocol, std::move(socket), transport_socket);
+}
+
+void NaiveProxy::DoConnect(unsigned int connection_id,
+                           ClientProtocol protocol,
+                           std::unique_ptr<StreamSocket> accepted_socket,
+                           StreamSocket* transport_socket) {
   std::unique_ptr<StreamSocket> socket;
   auto* proxy_delegate =
       static_cast<NaiveProxyDelegate*>(session_->context().proxy_delegate);
@@ -108,28 +261,36 @@ void NaiveProxy::DoConnect() {
   DCHECK(!proxy_info_.is_empty());
   const auto& proxy_server = proxy_info_.proxy_server();
   auto padding_detector_delegate = std::make_unique<PaddingDetectorDelegate>(
-      proxy_delegate, proxy_server, protocol_);
+      proxy_delegate, proxy_server, protocol);
 
-  if (protocol_ == ClientProtocol::kSocks5) {
-    socket = std::make_unique<Socks5ServerSocket>(std::move(accepted_socket_),
+  if (protocol == ClientProtocol::kSocks5) {
+    socket = std::make_unique<Socks5ServerSocket>(std::move(accepted_socket),
                                                   listen_user_, listen_pass_,
                                                   traffic_annotation_);
-  } else if (protocol_ == ClientProtocol::kHttp) {
-    socket = std::make_unique<HttpProxySocket>(std::move(accepted_socket_),
-                                               padding_detector_delegate.get(),
-                                               traffic_annotation_);
-  } else if (protocol_ == ClientProtocol::kRedir) {
-    socket = std::move(accepted_socket_);
+  } else if (protocol == ClientProtocol::kHttp) {
+    // Plain http listeners keep ignoring the listen URL's user info, unless
+    // it guards reverse tunnels.
+    bool check_auth = protocol_ == ClientProtocol::kAuto || reverse_listener_;
+    socket = std::make_unique<HttpProxySocket>(
+        std::move(accepted_socket), check_auth ? listen_user_ : std::string(),
+        check_auth ? listen_pass_ : std::string(),
+        padding_detector_delegate.get(), compression_, reverse_listener_.get(),
+        traffic_annotation_);
+  } else if (protocol == ClientProtocol::kRedir) {
+    socket = std::move(accepted_socket);
   } else {
     return;
   }
 
-  last_id_++;
-  const auto& nik = network_isolation_keys_[last_id_ % concurrency_];
+  const auto& nik = GetNetworkIsolationKey(connection_id);
+  UploadScheduler* upload_scheduler = nullptr;
+  if (!upload_schedulers_.empty())
+    upload_scheduler = upload_schedulers_[connection_id % concurrency_].get();
   auto connection_ptr = std::make_unique<NaiveConnection>(
-      last_id_, protocol_, std::move(padding_detector_delegate), proxy_info_,
-      server_ssl_config_, proxy_ssl_config_, resolver_, session_, nik, net_log_,
-      std::move(socket), traffic_annotation_);
+      connection_id, protocol, std::move(padding_detector_delegate),
+      proxy_info_, server_ssl_config_, proxy_ssl_config_, resolver_,
+      nat64_detector_, session_, nik, upload_scheduler, buffer_budget_.get(),
+      net_log_, std::move(socket), transport_socket, traffic_annotation_);
   auto* connection = connection_ptr.get();
   connection_by_id_[connection->id()] = std::move(connection_ptr);
   int result = connection->Connect(
@@ -156,6 +317,7 @@ void NaiveProxy::HandleConnectResult(NaiveConnection* connection, int result) {
 }
 
 void NaiveProxy::DoRun(NaiveConnection* connection) {
+  running_ids_.insert(connection->id());
   int result = connection->Run(
       base::BindRepeating(&NaiveProxy::OnRunComplete,
                           weak_ptr_factory_.GetWeakPtr(), connection->id()));
@@ -175,6 +337,58 @@ void NaiveProxy::HandleRunResult(NaiveConnection* connection, int result) {
   Close(connection->id(), result);
 }
 
+void NaiveProxy::DoReverse(size_t forward_index) {
+  if (draining_)
+    return;
+  unsigned int connection_id = ++last_id_;
+  auto* proxy_delegate =
+      static_cast<NaiveProxyDelegate*>(session_->context().proxy_delegate);
+  auto padding_detector_delegate = std::make_unique<PaddingDetectorDelegate>(
+      proxy_delegate, proxy_info_.proxy_server(), ClientProtocol::kReverse);
+
+  const auto& nik = GetNetworkIsolationKey(connection_id);
+  UploadScheduler* upload_scheduler = nullptr;
+  if (!upload_schedulers_.empty())
+    upload_scheduler = upload_schedulers_[connection_id % concurrency_].get();
+  auto connection_ptr = std::make_unique<NaiveConnection>(
+      connection_id, ClientProtocol::kReverse,
+      std::move(padding_detector_delegate), proxy_info_, server_ssl_config_,
+      proxy_ssl_config_, resolver_, nat64_detector_, session_, nik,
+      upload_scheduler, buffer_budget_.get(), net_log_,
+      /*accepted_socket=*/nullptr, /*transport_socket=*/nullptr,
+      traffic_annotation_);
+  auto* connection = connection_ptr.get();
+  connection->SetReverseForward(reverse_forwards_[forward_index]);
+  connection_by_id_[connection_id] = std::move(connection_ptr);
+  int result = connection->Connect(
+      base::BindRepeating(&NaiveProxy::OnReverseConnectComplete,
+                          weak_ptr_factory_.GetWeakPtr(), forward_index,
+                          connection_id));
+  if (result == ERR_IO_PENDING)
+    return;
+  OnReverseConnectComplete(forward_index, connection_id, result);
+}
+
+void NaiveProxy::OnReverseConnectComplete(size_t forward_index,
+                                          unsigned int connection_id,
+                                          int result) {
+  // Replaces the tunnel that stopped waiting. Waiting tunnels time out on
+  // the client side after a while, which is not an error here.
+  base::TimeDelta delay;
+  if (result != OK && result != ERR_TIMED_OUT)
+    delay = base::TimeDelta::FromSeconds(kReverseRetryDelaySeconds);
+  base::ThreadTaskRunnerHandle::Get()->PostDelayedTask(
+      FROM_HERE,
+      base::BindOnce(&NaiveProxy::DoReverse, weak_ptr_factory_.GetWeakPtr(),
+                     forward_index),
+      delay);
+
+  auto* connection = FindConnection(connection_id);
+  if (!connection)
+    return;
+  HandleConnectResult(connection, result);
+}
+
 void NaiveProxy::Close(unsigned int connection_id, int reason) {
   auto it = connection_by_id_.find(connection_id);
   if (it == connection_by_id_.end())
@@ -190,6 +404,11 @@ void NaiveProxy::Close(unsigned int connection_id, int reason) {
   base::ThreadTaskRunnerHandle::Get()->DeleteSoon(FROM_HERE,
                                                   std::move(it->second));
   connection_by_id_.erase(it);
+  running_ids_.erase(connection_id);
+}
+
+void NaiveProxy::Evict(unsigned int connection_id) {
+  Close(connection_id, ERR_INSUFFICIENT_RESOURCES);
 }
 
 NaiveConnection* NaiveProxy::FindConnection(unsigned int connection_id) {
@@ -199,4 +418,13 @@ NaiveConnection* NaiveProxy::FindConnection(unsigned int connection_id) {
   return it->second.get();
 }
 
+const NetworkIsolationKey& NaiveProxy::GetNetworkIsolationKey(
+    unsigned int connection_id) {
+  auto& nik = network_isolation_keys_[connection_id % concurrency_];
+  // Tunnels still connecting with the old key move to the spare as well.
+  if (spare_session_ && !spare_session_->HasSession(nik))
+    spare_session_->Take(&nik);
+  return nik;
+}
+
 }  // namespace net
@@ -6,8 +6,10 @@
 #ifndef NET_TOOLS_NAIVE_NAIVE_PROXY_H_
 #define NET_TOOLS_NAIVE_NAIVE_PROXY_H_
 
+#include <cstdint>
 #include <map>
 #include <memory>
+#include <set>
 #include <vector>
 
 #include "base/macros.h"
@@ -17,37 +19,79 @@
 #include "net/log/net_log_with_source.h"
 #include "net/proxy_resolution/proxy_info.h"
 #include "net/ssl/ssl_config.h"
+#include "net/tools/naive/buffer_budget.h"
 #include "net/tools/naive/naive_connection.h"
 #include "net/tools/naive/naive_protocol.h"
+#include "net/tools/naive/reverse_tunnel.h"
+#include "net/tools/naive/spare_session.h"
+#include "net/tools/naive/upload_scheduler.h"
 
 namespace net {
 
 class ClientSocketHandle;
 class HttpNetworkSession;
 class NaiveConnection;
+class ProtocolSniffingSocket;
 class ServerSocket;
 class StreamSocket;
 struct NetworkTrafficAnnotationTag;
 class RedirectResolver;
+class Nat64Detector;
 
 class NaiveProxy {
  public:
+  // |server_socket| may be null to only run reverse tunnels.
   NaiveProxy(std::unique_ptr<ServerSocket> server_socket,
              ClientProtocol protocol,
              const std::string& listen_user,
              const std::string& listen_pass,
              int concurrency,
+             bool compression,
+             int64_t buffer_memory,
              RedirectResolver* resolver,
+             Nat64Detector* nat64_detector,
              HttpNetworkSession* session,
              const NetworkTrafficAnnotationTag& traffic_annotation);
   ~NaiveProxy();
 
+  // Keeps tunnels waiting on the proxy server for connections to the remote
+  // port of |forward|.
+  void AddReverseForward(const ReverseForward& forward);
+
+  // Accepts reverse tunnels from clients for |ports| of |bind_address|.
+  void AllowReverseTunnels(const IPAddress& bind_address,
+                           const std::set<uint16_t>& ports);
+
+  // Keeps a spare HTTP/2 session to the proxy server for tunnels to switch
+  // to when their session is gone. The proxy must be HTTPS.
+  void KeepSpareSession();
+
+  // Stops accepting and replacing reverse tunnels, and closes connections
+  // that are not relaying yet. Relaying connections are left to finish.
+  void Drain();
+
+  // Stops accepting and closes all connections. They are destroyed in posted
+  // tasks, which have to run before this and the session are destroyed.
+  void CloseAll();
+
+  // Connections accepted and not yet closed.
+  size_t num_connections() const {
+    return sniffing_socket_by_id_.size() + connection_by_id_.size();
+  }
+
  private:
   void DoAcceptLoop();
   void OnAcceptComplete(int result);
   void HandleAcceptResult(int result);
 
-  void DoConnect();
+  void DoDetect();
+  void OnDetectComplete(unsigned int connection_id, int result);
+  void HandleDetectResult(unsigned int connection_id, int result);
+
+  void DoConnect(unsigned int connection_id,
+                 ClientProtocol protocol,
+                 std::unique_ptr<StreamSocket> accepted_socket,
+                 StreamSocket* transport_socket);
   void OnConnectComplete(unsigned int connection_id, int result);
   void HandleConnectResult(NaiveConnection* connection, int result);
 
@@ -55,19 +99,32 @@ class NaiveProxy {
   void OnRunComplete(unsigned int connection_id, int result);
   void HandleRunResult(NaiveConnection* connection, int result);
 
+  void DoReverse(size_t forward_index);
+  void OnReverseConnectComplete(size_t forward_index,
+                                unsigned int connection_id,
+                                int result);
+
   void Close(unsigned int connection_id, int reason);
+  void Evict(unsigned int connection_id);
 
   NaiveConnection* FindConnection(unsigned int connection_id);
 
+  // Picks the session of a new tunnel.
+  const NetworkIsolationKey& GetNetworkIsolationKey(
+      unsigned int connection_id);
+
   std::unique_ptr<ServerSocket> listen_socket_;
   ClientProtocol protocol_;
   std::string listen_user_;
   std::string listen_pass_;
   int concurrency_;
+  // Accepts compression from downstream naive clients.
+  bool compression_;
   ProxyInfo proxy_info_;
   SSLConfig server_ssl_config_;
   SSLConfig proxy_ssl_config_;
   RedirectResolver* resolver_;
+  Nat64Detector* nat64_detector_;
   HttpNetworkSession* session_;
   NetLogWithSource net_log_;
 
@@ -76,8 +133,28 @@ class NaiveProxy {
   std::unique_ptr<StreamSocket> accepted_socket_;
 
   std::vector<NetworkIsolationKey> network_isolation_keys_;
+  // Null unless a spare session is kept.
+  std::unique_ptr<SpareSession> spare_session_;
+
+  // One per upstream session, i.e. per network isolation key. Empty if
+  // tunnels don't share sessions, i.e. unless the proxy is HTTP/2 or QUIC.
+  std::vector<std::unique_ptr<UploadScheduler>> upload_schedulers_;
+
+  // Shared by all tunnels. Null if buffer memory is not limited.
+  std::unique_ptr<BufferBudget> buffer_budget_;
+
+  std::vector<ReverseForward> reverse_forwards_;
+  // Null if reverse tunnels are not allowed.
+  std::unique_ptr<ReverseListener> reverse_listener_;
+
+  std::map<unsigned int, std::unique_ptr<ProtocolSniffingSocket>>
+      sniffing_socket_by_id_;
 
   std::map<unsigned int, std::unique_ptr<NaiveConnection>> connection_by_id_;
+  // Connections past Connect(), relaying data.
+  std::set<unsigned int> running_ids_;
+
+  bool draining_;
 
   const NetworkTrafficAnnotationTag& traffic_annotation_;
 
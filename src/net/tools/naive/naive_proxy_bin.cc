@@ -6,10 +6,15 @@
 #include <cstdlib>
 #include <iostream>
 #include <limits>
+#include <map>
 #include <memory>
+#include <set>
 #include <string>
+#include <utility>
+#include <vector>
 
 #include "base/at_exit.h"
+#include "base/bind.h"
 #include "base/command_line.h"
 #include "base/feature_list.h"
 #include "base/files/file_path.h"
@@ -17,19 +22,25 @@
 #include "base/json/json_writer.h"
 #include "base/logging.h"
 #include "base/macros.h"
+#include "base/notreached.h"
 #include "base/rand_util.h"
 #include "base/run_loop.h"
 #include "base/strings/escape.h"
+#include "base/strings/strcat.h"
 #include "base/strings/string_number_conversions.h"
+#include "base/strings/string_util.h"
 #include "base/strings/stringprintf.h"
 #include "base/strings/utf_string_conversions.h"
 #include "base/system/sys_info.h"
 #include "base/task/single_thread_task_executor.h"
 #include "base/task/thread_pool/thread_pool_instance.h"
+#include "base/time/time.h"
+#include "base/timer/timer.h"
 #include "base/values.h"
 #include "build/build_config.h"
 #include "components/version_info/version_info.h"
 #include "net/base/auth.h"
+#include "net/base/host_port_pair.h"
 #include "net/base/network_isolation_key.h"
 #include "net/base/url_util.h"
 #include "net/cert/cert_verifier.h"
@@ -57,10 +68,18 @@
 #include "net/socket/udp_server_socket.h"
 #include "net/ssl/ssl_key_logger_impl.h"
 #include "net/third_party/quiche/src/quic/core/quic_versions.h"
+#include "net/tools/naive/admin_server.h"
+#include "net/tools/naive/dscp_socket_factory.h"
+#include "net/tools/naive/host_log.h"
 #include "net/tools/naive/naive_protocol.h"
 #include "net/tools/naive/naive_proxy.h"
 #include "net/tools/naive/naive_proxy_delegate.h"
+#include "net/tools/naive/nat64_detector.h"
+#include "net/tools/naive/nat64_test.h"
+#include "net/tools/naive/profile_schedule.h"
 #include "net/tools/naive/redirect_resolver.h"
+#include "net/tools/naive/reverse_tunnel.h"
+#include "net/tools/naive/soak_test.h"
 #include "net/traffic_annotation/network_traffic_annotation.h"
 #include "net/url_request/url_request_context.h"
 #include "net/url_request/url_request_context_builder.h"
@@ -78,6 +97,25 @@ constexpr int kListenBackLog = 512;
 constexpr int kDefaultMaxSocketsPerPool = 256;
 constexpr int kDefaultMaxSocketsPerGroup = 255;
 constexpr int kExpectedMaxUsers = 8;
+constexpr int kDefaultSoakTunnels = 1000;
+constexpr int kSoakRounds = 3;
+constexpr int kProfileSwitchCheckSeconds = 10;
+// Tunnels of the previous profile are closed after this.
+constexpr int kDrainSeconds = 5 * 60;
+constexpr int64_t kMiB = 1024 * 1024;
+constexpr char kMaskedSecret[] = "***";
+// Keys read by GetProfileCommandLine().
+constexpr const char* kProfileKeys[] = {
+    "listen",
+    "proxy",
+    "concurrency",
+    "extra-headers",
+    "host-resolver-rules",
+    "resolver-range",
+    "nat64-prefix",
+    "dscp",
+    "dscp-rules",
+};
 constexpr net::NetworkTrafficAnnotationTag kTrafficAnnotation =
     net::DefineNetworkTrafficAnnotation("naive", "");
 
@@ -88,10 +126,31 @@ struct CommandLine {
   std::string extra_headers;
   std::string host_resolver_rules;
   std::string resolver_range;
+  std::string nat64_prefix;
+  std::string dscp;
+  std::string dscp_rules;
+  bool no_padding;
+  bool compression;
+  bool spare_session;
+  bool ignore_certificate_errors;
+  std::string buffer_memory;
+  std::string reverse;
+  std::string reverse_bind;
+  std::string reverse_ports;
   bool no_log;
   base::FilePath log;
+  std::string log_host;
   base::FilePath log_net_log;
   base::FilePath ssl_key_log_file;
+  std::string admin;
+};
+
+struct Profiles {
+  // Command lines of named profiles, each derived from the top level one.
+  std::map<std::string, CommandLine> cmdlines;
+  // Used outside of the schedule. Empty means the top level command line.
+  std::string default_profile;
+  net::ProfileSchedule schedule;
 };
 
 struct Params {
@@ -108,9 +167,29 @@ struct Params {
   std::string host_resolver_rules;
   net::IPAddress resolver_range;
   size_t resolver_prefix;
+  // Empty unless configured.
+  net::IPAddress nat64_prefix;
+  size_t nat64_prefix_length;
+  net::DiffServCodePoint dscp;
+  std::vector<net::DscpRule> dscp_rules;
+  bool no_padding;
+  bool compression;
+  bool spare_session;
+  bool ignore_certificate_errors;
+  // In bytes, 0 means unlimited.
+  int64_t buffer_memory;
+  std::vector<net::ReverseForward> reverse_forwards;
+  // Only runs reverse tunnels, without a listener.
+  bool no_listen;
+  // Empty if reverse tunnels from clients are not allowed.
+  net::IPAddress reverse_bind;
+  std::set<uint16_t> reverse_ports;
   logging::LoggingSettings log_settings;
+  net::HostLogMode host_log_mode;
   base::FilePath net_log_path;
   base::FilePath ssl_key_path;
+  // 0 means no admin listener.
+  int admin_port;
 };
 
 std::unique_ptr<base::Value> GetConstants() {
@@ -135,15 +214,35 @@ void GetCommandLine(const base::CommandLine& proc, CommandLine* cmdline) {
                  "--listen=<proto>://[addr][:port]\n"
                  "                           proto: socks, http\n"
                  "                                  redir (Linux only)\n"
+                 "                                  auto\n"
                  "--proxy=<proto>://[<user>:<pass>@]<hostname>[:<port>]\n"
                  "                           proto: https, quic\n"
                  "--concurrency=<N>          Use N connections, less secure\n"
                  "--extra-headers=...        Extra headers split by CRLF\n"
                  "--host-resolver-rules=...  Resolver rules\n"
                  "--resolver-range=...       Redirect resolver range\n"
+                 "--nat64-prefix=<prefix>    NAT64 prefix of the network\n"
+                 "--dscp=<value>             DSCP of outgoing connections\n"
+                 "--dscp-rules=...           DSCP by destination address\n"
+                 "--no-padding               Plain HTTP/2 CONNECT, no padding\n"
+                 "--compression              Compress tunnels to/from naive\n"
+                 "--spare-session            Keep a spare HTTPS proxy session\n"
+                 "--ignore-certificate-errors\n"
+                 "                           Skip proxy cert checks, testing\n"
+                 "--buffer-memory=<MB>       Limit memory of tunnel buffers\n"
+                 "--reverse=<port>:<host>:<port>[,...]\n"
+                 "                           Forward proxy server ports here\n"
+                 "--reverse-bind=<addr>      Accept reverse tunnels on addr\n"
+                 "--reverse-ports=<port>[,...]\n"
+                 "                           Allowed reverse tunnel ports\n"
                  "--log[=<path>]             Log to stderr, or file\n"
+                 "--log-host=<mode>          Hostnames in log: full,\n"
+                 "                           truncated-hash, off\n"
                  "--log-net-log=<path>       Save NetLog\n"
                  "--ssl-key-log-file=<path>  Save SSL keys for Wireshark\n"
+                 "--profile=<name>           Profile in config.json\n"
+                 "--admin=<port>             Switch profiles via local port\n"
+                 "--dump-config              Print effective config\n"
               << std::endl;
     exit(EXIT_SUCCESS);
   }
@@ -160,57 +259,115 @@ void GetCommandLine(const base::CommandLine& proc, CommandLine* cmdline) {
   cmdline->host_resolver_rules =
       proc.GetSwitchValueASCII("host-resolver-rules");
   cmdline->resolver_range = proc.GetSwitchValueASCII("resolver-range");
+  cmdline->nat64_prefix = proc.GetSwitchValueASCII("nat64-prefix");
+  cmdline->dscp = proc.GetSwitchValueASCII("dscp");
+  cmdline->dscp_rules = proc.GetSwitchValueASCII("dscp-rules");
+  cmdline->no_padding = proc.HasSwitch("no-padding");
+  cmdline->compression = proc.HasSwitch("compression");
+  cmdline->spare_session = proc.HasSwitch("spare-session");
+  cmdline->ignore_certificate_errors =
+      proc.HasSwitch("ignore-certificate-errors");
+  cmdline->buffer_memory = proc.GetSwitchValueASCII("buffer-memory");
+  cmdline->reverse = proc.GetSwitchValueASCII("reverse");
+  cmdline->reverse_bind = proc.GetSwitchValueASCII("reverse-bind");
+  cmdline->reverse_ports = proc.GetSwitchValueASCII("reverse-ports");
   cmdline->no_log = !proc.HasSwitch("log");
   cmdline->log = proc.GetSwitchValuePath("log");
+  cmdline->log_host = proc.GetSwitchValueASCII("log-host");
   cmdline->log_net_log = proc.GetSwitchValuePath("log-net-log");
   cmdline->ssl_key_log_file = proc.GetSwitchValuePath("ssl-key-log-file");
+  cmdline->admin = proc.GetSwitchValueASCII("admin");
 }
 
-void GetCommandLineFromConfig(const base::FilePath& config_path,
-                              CommandLine* cmdline) {
-  JSONFileValueDeserializer reader(config_path);
-  int error_code;
-  std::string error_message;
-  auto value = reader.Deserialize(&error_code, &error_message);
-  if (value == nullptr) {
-    std::cerr << "Error reading " << config_path << ": (" << error_code << ") "
-              << error_message << std::endl;
-    exit(EXIT_FAILURE);
-  }
-  if (!value->is_dict()) {
-    std::cerr << "Invalid config format" << std::endl;
-    exit(EXIT_FAILURE);
-  }
-  const auto* listen = value->FindStringKey("listen");
+// Reads the keys that profiles can override.
+void GetProfileCommandLine(const base::Value& value, CommandLine* cmdline) {
+  const auto* listen = value.FindStringKey("listen");
   if (listen) {
     cmdline->listen = *listen;
   }
-  const auto* proxy = value->FindStringKey("proxy");
+  const auto* proxy = value.FindStringKey("proxy");
   if (proxy) {
     cmdline->proxy = *proxy;
   }
-  const auto* concurrency = value->FindStringKey("concurrency");
+  const auto* concurrency = value.FindStringKey("concurrency");
   if (concurrency) {
     cmdline->concurrency = *concurrency;
   }
-  const auto* extra_headers = value->FindStringKey("extra-headers");
+  const auto* extra_headers = value.FindStringKey("extra-headers");
   if (extra_headers) {
     cmdline->extra_headers = *extra_headers;
   }
-  const auto* host_resolver_rules = value->FindStringKey("host-resolver-rules");
+  const auto* host_resolver_rules = value.FindStringKey("host-resolver-rules");
   if (host_resolver_rules) {
     cmdline->host_resolver_rules = *host_resolver_rules;
   }
-  const auto* resolver_range = value->FindStringKey("resolver-range");
+  const auto* resolver_range = value.FindStringKey("resolver-range");
   if (resolver_range) {
     cmdline->resolver_range = *resolver_range;
   }
+  const auto* nat64_prefix = value.FindStringKey("nat64-prefix");
+  if (nat64_prefix) {
+    cmdline->nat64_prefix = *nat64_prefix;
+  }
+  const auto* dscp = value.FindStringKey("dscp");
+  if (dscp) {
+    cmdline->dscp = *dscp;
+  }
+  const auto* dscp_rules = value.FindStringKey("dscp-rules");
+  if (dscp_rules) {
+    cmdline->dscp_rules = *dscp_rules;
+  }
+}
+
+void GetCommandLineFromConfig(const base::FilePath& config_path,
+                              CommandLine* cmdline,
+                              Profiles* profiles) {
+  JSONFileValueDeserializer reader(config_path);
+  int error_code;
+  std::string error_message;
+  auto value = reader.Deserialize(&error_code, &error_message);
+  if (value == nullptr) {
+    std::cerr << "Error reading " << config_path << ": (" << error_code << ") "
+              << error_message << std::endl;
+    exit(EXIT_FAILURE);
+  }
+  if (!value->is_dict()) {
+    std::cerr << "Invalid config format" << std::endl;
+    exit(EXIT_FAILURE);
+  }
+  GetProfileCommandLine(*value, cmdline);
+  cmdline->no_padding = value->FindBoolKey("no-padding").value_or(false);
+  cmdline->compression = value->FindBoolKey("compression").value_or(false);
+  cmdline->spare_session =
+      value->FindBoolKey("spare-session").value_or(false);
+  cmdline->ignore_certificate_errors =
+      value->FindBoolKey("ignore-certificate-errors").value_or(false);
+  const auto* buffer_memory = value->FindStringKey("buffer-memory");
+  if (buffer_memory) {
+    cmdline->buffer_memory = *buffer_memory;
+  }
+  const auto* reverse = value->FindStringKey("reverse");
+  if (reverse) {
+    cmdline->reverse = *reverse;
+  }
+  const auto* reverse_bind = value->FindStringKey("reverse-bind");
+  if (reverse_bind) {
+    cmdline->reverse_bind = *reverse_bind;
+  }
+  const auto* reverse_ports = value->FindStringKey("reverse-ports");
+  if (reverse_ports) {
+    cmdline->reverse_ports = *reverse_ports;
+  }
   cmdline->no_log = true;
   const auto* log = value->FindStringKey("log");
   if (log) {
     cmdline->no_log = false;
     cmdline->log = base::FilePath::FromUTF8Unsafe(*log);
   }
+  const auto* log_host = value->FindStringKey("log-host");
+  if (log_host) {
+    cmdline->log_host = *log_host;
+  }
   const auto* log_net_log = value->FindStringKey("log-net-log");
   if (log_net_log) {
     cmdline->log_net_log = base::FilePath::FromUTF8Unsafe(*log_net_log);
@@ -220,6 +377,35 @@ void GetCommandLineFromConfig(const base::FilePath& config_path,
     cmdline->ssl_key_log_file =
         base::FilePath::FromUTF8Unsafe(*ssl_key_log_file);
   }
+  const auto* admin = value->FindStringKey("admin");
+  if (admin) {
+    cmdline->admin = *admin;
+  }
+
+  const auto* profile_dicts = value->FindDictKey("profiles");
+  if (profile_dicts) {
+    for (const auto& item : profile_dicts->DictItems()) {
+      if (item.first.empty() || !item.second.is_dict()) {
+        std::cerr << "Invalid profile " << item.first << std::endl;
+        exit(EXIT_FAILURE);
+      }
+      CommandLine profile_cmdline = *cmdline;
+      GetProfileCommandLine(item.second, &profile_cmdline);
+      profiles->cmdlines[item.first] = profile_cmdline;
+    }
+  }
+  const auto* profile = value->FindStringKey("profile");
+  if (profile) {
+    profiles->default_profile = *profile;
+  }
+  const auto* schedule = value->FindKey("schedule");
+  if (schedule) {
+    std::string error;
+    if (!profiles->schedule.Parse(*schedule, &error)) {
+      std::cerr << error << std::endl;
+      exit(EXIT_FAILURE);
+    }
+  }
 }
 
 std::string GetProxyFromURL(const GURL& url) {
@@ -230,13 +416,201 @@ std::string GetProxyFromURL(const GURL& url) {
   return str;
 }
 
+const char* ClientProtocolToScheme(net::ClientProtocol protocol) {
+  switch (protocol) {
+    case net::ClientProtocol::kSocks5:
+      return "socks";
+    case net::ClientProtocol::kHttp:
+      return "http";
+    case net::ClientProtocol::kRedir:
+      return "redir";
+    case net::ClientProtocol::kAuto:
+      return "auto";
+    case net::ClientProtocol::kReverse:
+      break;
+  }
+  NOTREACHED();
+  return "";
+}
+
+base::Value PathToValue(const base::FilePath& path) {
+  if (path.empty())
+    return base::Value();
+  return base::Value(path.AsUTF8Unsafe());
+}
+
+// Returns the options in |params| with defaults filled in and secrets masked,
+// using the keys of the config file.
+base::Value GetConfigValue(const Params& params) {
+  base::Value config(base::Value::Type::DICTIONARY);
+
+  std::string listen =
+      base::StrCat({ClientProtocolToScheme(params.protocol), "://"});
+  if (!params.listen_user.empty() || !params.listen_pass.empty()) {
+    base::StrAppend(
+        &listen, {params.listen_user, ":",
+                  params.listen_pass.empty() ? "" : kMaskedSecret, "@"});
+  }
+  listen += net::HostPortPair(params.listen_addr, params.listen_port)
+                .ToString();
+  if (!params.no_listen) {
+    config.SetStringKey("listen", listen);
+  } else {
+    config.SetKey("listen", base::Value());
+  }
+
+  std::string proxy = params.proxy_url;
+  size_t auth_pos = proxy.find("://");
+  if ((!params.proxy_user.empty() || !params.proxy_pass.empty()) &&
+      auth_pos != std::string::npos) {
+    proxy.insert(auth_pos + 3,
+                 base::StrCat({base::UTF16ToUTF8(params.proxy_user), ":",
+                               params.proxy_pass.empty() ? "" : kMaskedSecret,
+                               "@"}));
+  }
+  config.SetStringKey("proxy", proxy);
+
+  config.SetStringKey("concurrency",
+                      base::NumberToString(params.concurrency));
+
+  std::vector<std::string> headers;
+  for (net::HttpRequestHeaders::Iterator it(params.extra_headers);
+       it.GetNext();) {
+    bool secret =
+        base::EqualsCaseInsensitiveASCII(it.name(), "authorization") ||
+        base::EqualsCaseInsensitiveASCII(it.name(), "proxy-authorization") ||
+        base::EqualsCaseInsensitiveASCII(it.name(), "cookie");
+    headers.push_back(base::StrCat(
+        {it.name(), ": ", secret ? kMaskedSecret : it.value()}));
+  }
+  config.SetStringKey("extra-headers", base::JoinString(headers, "\r\n"));
+
+  config.SetStringKey("host-resolver-rules", params.host_resolver_rules);
+
+  if (params.protocol == net::ClientProtocol::kRedir ||
+      params.protocol == net::ClientProtocol::kAuto) {
+    config.SetStringKey("resolver-range",
+                        base::StrCat({params.resolver_range.ToString(), "/",
+                                      base::NumberToString(
+                                          params.resolver_prefix)}));
+  } else {
+    config.SetKey("resolver-range", base::Value());
+  }
+
+  if (!params.nat64_prefix.empty()) {
+    config.SetStringKey("nat64-prefix",
+                        base::StrCat({params.nat64_prefix.ToString(), "/",
+                                      base::NumberToString(
+                                          params.nat64_prefix_length)}));
+  } else {
+    config.SetKey("nat64-prefix", base::Value());
+  }
+
+  if (params.dscp != net::DSCP_NO_CHANGE) {
+    config.SetStringKey("dscp", base::NumberToString(params.dscp));
+  } else {
+    config.SetKey("dscp", base::Value());
+  }
+  config.SetStringKey("dscp-rules", net::DscpRulesToString(params.dscp_rules));
+
+  config.SetBoolKey("no-padding", params.no_padding);
+  config.SetBoolKey("compression", params.compression);
+  config.SetBoolKey("spare-session", params.spare_session);
+  config.SetBoolKey("ignore-certificate-errors",
+                    params.ignore_certificate_errors);
+  if (params.buffer_memory > 0) {
+    config.SetStringKey("buffer-memory",
+                        base::NumberToString(params.buffer_memory / kMiB));
+  } else {
+    config.SetKey("buffer-memory", base::Value());
+  }
+
+  std::vector<std::string> reverse_forwards;
+  for (const auto& forward : params.reverse_forwards) {
+    reverse_forwards.push_back(forward.ToString());
+  }
+  if (!reverse_forwards.empty()) {
+    config.SetStringKey("reverse", base::JoinString(reverse_forwards, ","));
+  } else {
+    config.SetKey("reverse", base::Value());
+  }
+  if (!params.reverse_bind.empty()) {
+    config.SetStringKey("reverse-bind", params.reverse_bind.ToString());
+  } else {
+    config.SetKey("reverse-bind", base::Value());
+  }
+  std::vector<std::string> reverse_ports;
+  for (uint16_t port : params.reverse_ports) {
+    reverse_ports.push_back(base::NumberToString(port));
+  }
+  if (!reverse_ports.empty()) {
+    config.SetStringKey("reverse-ports", base::JoinString(reverse_ports, ","));
+  } else {
+    config.SetKey("reverse-ports", base::Value());
+  }
+
+  switch (params.log_settings.logging_dest) {
+    case logging::LOG_NONE:
+      config.SetKey("log", base::Value());
+      break;
+    case logging::LOG_TO_FILE:
+      config.SetKey("log", PathToValue(base::FilePath(
+                               params.log_settings.log_file_path)));
+      break;
+    default:
+      // Empty means stderr.
+      config.SetStringKey("log", "");
+      break;
+  }
+  config.SetStringKey("log-host",
+                      net::HostLogModeToString(params.host_log_mode));
+  config.SetKey("log-net-log", PathToValue(params.net_log_path));
+  config.SetKey("ssl-key-log-file", PathToValue(params.ssl_key_path));
+  if (params.admin_port != 0) {
+    config.SetStringKey("admin", base::NumberToString(params.admin_port));
+  } else {
+    config.SetKey("admin", base::Value());
+  }
+
+  return config;
+}
+
+// Prints the effective config of all profiles as JSON with sorted keys.
+void DumpConfig(const std::map<std::string, Params>& params_by_profile,
+                const Profiles& profiles) {
+  base::Value config = GetConfigValue(params_by_profile.at(""));
+
+  base::Value profile_dicts(base::Value::Type::DICTIONARY);
+  for (const auto& item : params_by_profile) {
+    if (item.first.empty())
+      continue;
+    base::Value profile_config = GetConfigValue(item.second);
+    base::Value profile_dict(base::Value::Type::DICTIONARY);
+    for (const char* key : kProfileKeys) {
+      // Keys that GetConfigValue() does not write are left out.
+      base::Value* value = profile_config.FindKey(key);
+      if (value)
+        profile_dict.SetKey(key, std::move(*value));
+    }
+    profile_dicts.SetKey(item.first, std::move(profile_dict));
+  }
+  if (!profile_dicts.DictEmpty())
+    config.SetKey("profiles", std::move(profile_dicts));
+  if (!profiles.default_profile.empty())
+    config.SetStringKey("profile", profiles.default_profile);
+  if (!profiles.schedule.empty())
+    config.SetKey("schedule", profiles.schedule.ToValue());
+
+  std::string json;
+  base::JSONWriter::WriteWithOptions(
+      config, base::JSONWriter::OPTIONS_PRETTY_PRINT, &json);
+  std::cout << json << std::flush;
+}
+
 bool ParseCommandLine(const CommandLine& cmdline, Params* params) {
   params->protocol = net::ClientProtocol::kSocks5;
   params->listen_addr = "0.0.0.0";
   params->listen_port = 1080;
-  url::AddStandardScheme("socks",
-                         url::SCHEME_WITH_HOST_PORT_AND_USER_INFORMATION);
-  url::AddStandardScheme("redir", url::SCHEME_WITH_HOST_AND_PORT);
   if (!cmdline.listen.empty()) {
     GURL url(cmdline.listen);
     if (url.scheme() == "socks") {
@@ -253,6 +627,9 @@ bool ParseCommandLine(const CommandLine& cmdline, Params* params) {
       std::cerr << "Redir protocol only supports Linux." << std::endl;
       return false;
 #endif
+    } else if (url.scheme() == "auto") {
+      params->protocol = net::ClientProtocol::kAuto;
+      params->listen_port = 1080;
     } else {
       std::cerr << "Invalid scheme in --listen" << std::endl;
       return false;
@@ -308,7 +685,80 @@ bool ParseCommandLine(const CommandLine& cmdline, Params* params) {
 
   params->host_resolver_rules = cmdline.host_resolver_rules;
 
-  if (params->protocol == net::ClientProtocol::kRedir) {
+  params->dscp = net::DSCP_NO_CHANGE;
+  if (!cmdline.dscp.empty()) {
+    if (!net::ParseDiffServCodePoint(cmdline.dscp, &params->dscp)) {
+      std::cerr << "Invalid DSCP" << std::endl;
+      return false;
+    }
+  }
+  if (!net::ParseDscpRules(cmdline.dscp_rules, &params->dscp_rules)) {
+    std::cerr << "Invalid DSCP rules" << std::endl;
+    return false;
+  }
+
+  params->no_padding = cmdline.no_padding;
+  params->compression = cmdline.compression;
+
+  params->spare_session = cmdline.spare_session;
+  if (params->spare_session &&
+      !base::StartsWith(params->proxy_url, "https://")) {
+    std::cerr << "Spare session needs an HTTPS proxy" << std::endl;
+    return false;
+  }
+  params->ignore_certificate_errors = cmdline.ignore_certificate_errors;
+
+  params->buffer_memory = 0;
+  if (!cmdline.buffer_memory.empty()) {
+    int buffer_memory;
+    if (!base::StringToInt(cmdline.buffer_memory, &buffer_memory) ||
+        buffer_memory < 1) {
+      std::cerr << "Invalid buffer memory" << std::endl;
+      return false;
+    }
+    params->buffer_memory = static_cast<int64_t>(buffer_memory) * kMiB;
+  }
+
+  if (!net::ParseReverseForwards(cmdline.reverse,
+                                 &params->reverse_forwards)) {
+    std::cerr << "Invalid reverse forward" << std::endl;
+    return false;
+  }
+  if (!params->reverse_forwards.empty() &&
+      !base::StartsWith(params->proxy_url, "http://") &&
+      !base::StartsWith(params->proxy_url, "https://")) {
+    std::cerr << "Reverse forwards need an HTTP proxy" << std::endl;
+    return false;
+  }
+  params->no_listen =
+      cmdline.listen.empty() && !params->reverse_forwards.empty();
+
+  if (!cmdline.reverse_bind.empty() &&
+      !params->reverse_bind.AssignFromIPLiteral(cmdline.reverse_bind)) {
+    std::cerr << "Invalid reverse bind address" << std::endl;
+    return false;
+  }
+  if (!net::ParseReversePorts(cmdline.reverse_ports,
+                              &params->reverse_ports)) {
+    std::cerr << "Invalid reverse ports" << std::endl;
+    return false;
+  }
+  if (params->reverse_bind.empty() != params->reverse_ports.empty()) {
+    std::cerr << "Reverse bind and reverse ports go together" << std::endl;
+    return false;
+  }
+  // Only clients with the listener's credentials may take the ports.
+  if (!params->reverse_bind.empty() &&
+      ((params->protocol != net::ClientProtocol::kHttp &&
+        params->protocol != net::ClientProtocol::kAuto) ||
+       (params->listen_user.empty() && params->listen_pass.empty()))) {
+    std::cerr << "Reverse tunnels need an HTTP listener with credentials"
+              << std::endl;
+    return false;
+  }
+
+  if (params->protocol == net::ClientProtocol::kRedir ||
+      params->protocol == net::ClientProtocol::kAuto) {
     std::string range = "100.64.0.0/10";
     if (!cmdline.resolver_range.empty())
       range = cmdline.resolver_range;
@@ -322,6 +772,15 @@ bool ParseCommandLine(const CommandLine& cmdline, Params* params) {
       std::cerr << "IPv6 resolver range not supported" << std::endl;
       return false;
     }
+
+    if (!cmdline.nat64_prefix.empty() &&
+        (!net::ParseCIDRBlock(cmdline.nat64_prefix, &params->nat64_prefix,
+                              &params->nat64_prefix_length) ||
+         !net::Nat64Detector::IsValidPrefix(params->nat64_prefix,
+                                            params->nat64_prefix_length))) {
+      std::cerr << "Invalid NAT64 prefix" << std::endl;
+      return false;
+    }
   }
 
   if (!cmdline.no_log) {
@@ -335,9 +794,26 @@ bool ParseCommandLine(const CommandLine& cmdline, Params* params) {
     params->log_settings.logging_dest = logging::LOG_NONE;
   }
 
+  params->host_log_mode = net::HostLogMode::kFull;
+  if (!cmdline.log_host.empty()) {
+    if (!net::ParseHostLogMode(cmdline.log_host, &params->host_log_mode)) {
+      std::cerr << "Invalid log host mode" << std::endl;
+      return false;
+    }
+  }
+
   params->net_log_path = cmdline.log_net_log;
   params->ssl_key_path = cmdline.ssl_key_log_file;
 
+  params->admin_port = 0;
+  if (!cmdline.admin.empty()) {
+    if (!base::StringToInt(cmdline.admin, &params->admin_port) ||
+        params->admin_port <= 0 || params->admin_port > UINT16_MAX) {
+      std::cerr << "Invalid admin port" << std::endl;
+      return false;
+    }
+  }
+
   return true;
 }
 }  // namespace
@@ -408,12 +884,17 @@ std::unique_ptr<URLRequestContext> BuildCertURLRequestContext(NetLog* net_log) {
 std::unique_ptr<URLRequestContext> BuildURLRequestContext(
     const Params& params,
     scoped_refptr<CertNetFetcherURLRequest> cert_net_fetcher,
+    ClientSocketFactory* socket_factory,
     NetLog* net_log) {
   URLRequestContextBuilder builder;
 
   builder.DisableHttpCache();
   builder.set_net_log(net_log);
 
+  if (socket_factory) {
+    builder.set_client_socket_factory(socket_factory);
+  }
+
   ProxyConfig proxy_config;
   proxy_config.proxy_rules().ParseFromString(params.proxy_url);
   LOG(INFO) << "Proxying via " << params.proxy_url;
@@ -429,11 +910,18 @@ std::unique_ptr<URLRequestContext> BuildURLRequestContext(
     builder.set_host_mapping_rules(params.host_resolver_rules);
   }
 
+  if (params.ignore_certificate_errors) {
+    HttpNetworkSession::Params session_params;
+    session_params.ignore_certificate_errors = true;
+    builder.set_http_network_session_params(session_params);
+  }
+
   builder.SetCertVerifier(
       CertVerifier::CreateDefault(std::move(cert_net_fetcher)));
 
   builder.set_proxy_delegate(
-      std::make_unique<NaiveProxyDelegate>(params.extra_headers));
+      std::make_unique<NaiveProxyDelegate>(
+          params.extra_headers, !params.no_padding, params.compression));
 
   auto context = builder.Build();
 
@@ -461,9 +949,294 @@ std::unique_ptr<URLRequestContext> BuildURLRequestContext(
 }  // namespace
 }  // namespace net
 
+namespace {
+// The proxy of one profile.
+class ProxyInstance {
+ public:
+  ProxyInstance() = default;
+
+  // Returns false if it fails to start.
+  bool Start(const Params& params, net::NetLog* net_log);
+
+  // Stops listening and lets relaying tunnels finish, for up to
+  // kDrainSeconds.
+  void Drain();
+
+  bool IsDrained() const {
+    return naive_proxy_->num_connections() == 0 ||
+           base::TimeTicks::Now() >= drain_deadline_;
+  }
+
+  // Closes all connections. Runs the posted tasks destroying them, which
+  // still point into this, so must not be called from a task.
+  void Close();
+
+ private:
+  std::unique_ptr<net::URLRequestContext> cert_context_;
+  // Must outlive |context_|.
+  std::unique_ptr<net::DscpSocketFactory> socket_factory_;
+  std::unique_ptr<net::URLRequestContext> context_;
+  std::unique_ptr<net::Nat64Detector> nat64_detector_;
+  std::unique_ptr<net::RedirectResolver> resolver_;
+  std::unique_ptr<net::NaiveProxy> naive_proxy_;
+  base::TimeTicks drain_deadline_;
+
+  DISALLOW_COPY_AND_ASSIGN(ProxyInstance);
+};
+
+bool ProxyInstance::Start(const Params& params, net::NetLog* net_log) {
+  cert_context_ = net::BuildCertURLRequestContext(net_log);
+  scoped_refptr<net::CertNetFetcherURLRequest> cert_net_fetcher;
+#if defined(OS_LINUX) || defined(OS_MAC) || defined(OS_ANDROID)
+  cert_net_fetcher = base::MakeRefCounted<net::CertNetFetcherURLRequest>();
+  cert_net_fetcher->SetURLRequestContext(cert_context_.get());
+#endif
+  if (params.dscp != net::DSCP_NO_CHANGE || !params.dscp_rules.empty()) {
+    socket_factory_ = std::make_unique<net::DscpSocketFactory>(
+        params.dscp, params.dscp_rules);
+    if (params.dscp != net::DSCP_NO_CHANGE)
+      LOG(INFO) << "Marking outgoing connections with DSCP " << params.dscp;
+    if (!params.dscp_rules.empty()) {
+      LOG(INFO) << "Marking outgoing connections by DSCP rules "
+                << net::DscpRulesToString(params.dscp_rules);
+    }
+  }
+  context_ = net::BuildURLRequestContext(
+      params, std::move(cert_net_fetcher), socket_factory_.get(), net_log);
+  auto* session = context_->http_transaction_factory()->GetSession();
+
+  std::unique_ptr<net::TCPServerSocket> listen_socket;
+  int result;
+  if (!params.no_listen) {
+    listen_socket =
+        std::make_unique<net::TCPServerSocket>(net_log, net::NetLogSource());
+    result = listen_socket->ListenWithAddressAndPort(
+        params.listen_addr, params.listen_port, kListenBackLog);
+    if (result != net::OK) {
+      LOG(ERROR) << "Failed to listen: " << result;
+      return false;
+    }
+    LOG(INFO) << "Listening on " << params.listen_addr << ":"
+              << params.listen_port;
+  }
+
+  bool use_resolver = params.protocol == net::ClientProtocol::kRedir;
+#if defined(OS_LINUX)
+  use_resolver |= params.protocol == net::ClientProtocol::kAuto;
+#endif
+  if (use_resolver) {
+    auto resolver_socket =
+        std::make_unique<net::UDPServerSocket>(net_log, net::NetLogSource());
+    resolver_socket->AllowAddressReuse();
+    net::IPAddress listen_addr;
+    if (!listen_addr.AssignFromIPLiteral(params.listen_addr)) {
+      LOG(ERROR) << "Failed to open resolver: " << net::ERR_ADDRESS_INVALID;
+      return false;
+    }
+
+    result = resolver_socket->Listen(
+        net::IPEndPoint(listen_addr, params.listen_port));
+    if (result != net::OK) {
+      LOG(ERROR) << "Failed to open resolver: " << result;
+      return false;
+    }
+
+    nat64_detector_ =
+        std::make_unique<net::Nat64Detector>(context_->host_resolver());
+    if (!params.nat64_prefix.empty()) {
+      nat64_detector_->AddPrefix(params.nat64_prefix,
+                                 params.nat64_prefix_length);
+    }
+    resolver_ = std::make_unique<net::RedirectResolver>(
+        std::move(resolver_socket), params.resolver_range,
+        params.resolver_prefix, nat64_detector_.get());
+  }
+
+  naive_proxy_ = std::make_unique<net::NaiveProxy>(
+      std::move(listen_socket), params.protocol, params.listen_user,
+      params.listen_pass, params.concurrency, params.compression,
+      params.buffer_memory, resolver_.get(), nat64_detector_.get(), session,
+      kTrafficAnnotation);
+  for (const auto& forward : params.reverse_forwards) {
+    naive_proxy_->AddReverseForward(forward);
+  }
+  if (!params.reverse_bind.empty()) {
+    naive_proxy_->AllowReverseTunnels(params.reverse_bind,
+                                      params.reverse_ports);
+  }
+  if (params.spare_session) {
+    naive_proxy_->KeepSpareSession();
+  }
+  return true;
+}
+
+void ProxyInstance::Drain() {
+  naive_proxy_->Drain();
+  // Relaying connections have no more use for the resolver.
+  if (resolver_)
+    resolver_->Close();
+  drain_deadline_ =
+      base::TimeTicks::Now() + base::TimeDelta::FromSeconds(kDrainSeconds);
+}
+
+void ProxyInstance::Close() {
+  naive_proxy_->CloseAll();
+  base::RunLoop().RunUntilIdle();
+}
+
+// Runs the proxy of the active profile, switching profiles by the schedule
+// or on request.
+class ProfileSwitcher {
+ public:
+  ProfileSwitcher(const Profiles& profiles,
+                  const std::map<std::string, Params>& params_by_profile,
+                  net::NetLog* net_log);
+
+  // Returns only if a proxy fails to start.
+  void Run();
+
+  std::string active_profile() const { return active_profile_; }
+
+  // Switches to |profile| until the next scheduled switch. Returns false if
+  // |profile| is unknown.
+  bool SwitchTo(const std::string& profile);
+
+ private:
+  void Check(base::Time next_switch);
+
+  const Profiles& profiles_;
+  const std::map<std::string, Params>& params_by_profile_;
+  net::NetLog* net_log_;
+
+  std::string active_profile_;
+  std::unique_ptr<ProxyInstance> proxy_;
+  // Proxies of previous profiles, waiting for their tunnels to finish.
+  std::vector<std::unique_ptr<ProxyInstance>> draining_proxies_;
+
+  // Set by SwitchTo().
+  bool has_requested_profile_;
+  std::string requested_profile_;
+  base::Time requested_until_;
+
+  base::RepeatingClosure quit_;
+
+  DISALLOW_COPY_AND_ASSIGN(ProfileSwitcher);
+};
+
+ProfileSwitcher::ProfileSwitcher(
+    const Profiles& profiles,
+    const std::map<std::string, Params>& params_by_profile,
+    net::NetLog* net_log)
+    : profiles_(profiles),
+      params_by_profile_(params_by_profile),
+      net_log_(net_log),
+      has_requested_profile_(false) {}
+
+void ProfileSwitcher::Run() {
+  for (;;) {
+    base::Time now = base::Time::Now();
+    const auto& schedule = profiles_.schedule;
+    base::Time next_switch =
+        schedule.GetNextSwitch(now, profiles_.default_profile);
+    if (has_requested_profile_ && !requested_until_.is_null() &&
+        now >= requested_until_) {
+      has_requested_profile_ = false;
+    }
+    std::string profile =
+        has_requested_profile_
+            ? requested_profile_
+            : schedule.GetActiveProfile(now, profiles_.default_profile);
+
+    if (!proxy_ || profile != active_profile_) {
+      if (proxy_) {
+        LOG(INFO) << "Draining previous profile " << active_profile_;
+        proxy_->Drain();
+        draining_proxies_.push_back(std::move(proxy_));
+      }
+      if (!profile.empty())
+        LOG(INFO) << "Using profile " << profile;
+      active_profile_ = profile;
+      proxy_ = std::make_unique<ProxyInstance>();
+      if (!proxy_->Start(params_by_profile_.at(profile), net_log_))
+        return;
+    }
+
+    base::RunLoop run_loop;
+    quit_ = run_loop.QuitClosure();
+    // Polls the wall clock, as a timer for the whole delay runs on monotonic
+    // time, which stops during suspend.
+    base::RepeatingTimer check_timer;
+    if (!next_switch.is_null() || !draining_proxies_.empty()) {
+      check_timer.Start(
+          FROM_HERE, base::TimeDelta::FromSeconds(kProfileSwitchCheckSeconds),
+          base::BindRepeating(&ProfileSwitcher::Check, base::Unretained(this),
+                              next_switch));
+    }
+    run_loop.Run();
+    quit_.Reset();
+
+    for (auto it = draining_proxies_.begin();
+         it != draining_proxies_.end();) {
+      if ((*it)->IsDrained()) {
+        (*it)->Close();
+        it = draining_proxies_.erase(it);
+      } else {
+        ++it;
+      }
+    }
+  }
+}
+
+bool ProfileSwitcher::SwitchTo(const std::string& profile) {
+  if (!params_by_profile_.count(profile))
+    return false;
+  LOG(INFO) << "Switch to profile " << profile << " requested";
+  has_requested_profile_ = true;
+  requested_profile_ = profile;
+  requested_until_ = profiles_.schedule.GetNextSwitch(
+      base::Time::Now(), profiles_.default_profile);
+  if (quit_)
+    quit_.Run();
+  return true;
+}
+
+void ProfileSwitcher::Check(base::Time next_switch) {
+  bool drained = false;
+  for (const auto& proxy : draining_proxies_)
+    drained |= proxy->IsDrained();
+  if (drained || (!next_switch.is_null() && base::Time::Now() >= next_switch))
+    quit_.Run();
+}
+
+// Runs the soak test with |tunnels| concurrent tunnels per round, connecting
+// directly regardless of --proxy.
+bool RunSoak(const std::string& tunnels_str,
+             Params params,
+             net::NetLog* net_log) {
+  int tunnels = kDefaultSoakTunnels;
+  if (!tunnels_str.empty() &&
+      (!base::StringToInt(tunnels_str, &tunnels) || tunnels < 1)) {
+    std::cerr << "Invalid number of soak test tunnels" << std::endl;
+    return false;
+  }
+  params.proxy_url = "direct://";
+  params.proxy_user.clear();
+  params.proxy_pass.clear();
+  auto context = net::BuildURLRequestContext(params, nullptr, nullptr, net_log);
+  auto* session = context->http_transaction_factory()->GetSession();
+  return net::RunSoakTest(tunnels, kSoakRounds, session, net_log,
+                          kTrafficAnnotation);
+}
+}  // namespace
+
 int main(int argc, char* argv[]) {
   url::AddStandardScheme("quic",
                          url::SCHEME_WITH_HOST_PORT_AND_USER_INFORMATION);
+  url::AddStandardScheme("socks",
+                         url::SCHEME_WITH_HOST_PORT_AND_USER_INFORMATION);
+  url::AddStandardScheme("redir", url::SCHEME_WITH_HOST_AND_PORT);
+  url::AddStandardScheme("auto",
+                         url::SCHEME_WITH_HOST_PORT_AND_USER_INFORMATION);
   base::FeatureList::InitializeInstance(
       "PartitionConnectionsByNetworkIsolationKey", std::string());
   base::SingleThreadTaskExecutor io_task_executor(base::MessagePumpType::IO);
@@ -477,22 +1250,45 @@ int main(int argc, char* argv[]) {
   base::CommandLine::Init(argc, argv);
 
   CommandLine cmdline;
-  Params params;
+  Profiles profiles;
   const auto& proc = *base::CommandLine::ForCurrentProcess();
   const auto& args = proc.GetArgs();
+  bool dump_config = proc.HasSwitch("dump-config");
   if (args.empty()) {
-    if (proc.argv().size() >= 2) {
+    // Uses config.json if there are no other options.
+    if (proc.argv().size() >= (dump_config ? 3u : 2u)) {
       GetCommandLine(proc, &cmdline);
     } else {
       auto path = base::FilePath::FromUTF8Unsafe("config.json");
-      GetCommandLineFromConfig(path, &cmdline);
+      GetCommandLineFromConfig(path, &cmdline, &profiles);
     }
   } else {
     base::FilePath path(args[0]);
-    GetCommandLineFromConfig(path, &cmdline);
+    GetCommandLineFromConfig(path, &cmdline, &profiles);
   }
-  if (!ParseCommandLine(cmdline, &params)) {
-    return EXIT_FAILURE;
+  if (proc.HasSwitch("profile")) {
+    profiles.default_profile = proc.GetSwitchValueASCII("profile");
+  }
+  profiles.cmdlines[""] = cmdline;
+  std::vector<std::string> profile_names = profiles.schedule.GetProfiles();
+  profile_names.push_back(profiles.default_profile);
+  for (const auto& name : profile_names) {
+    if (!profiles.cmdlines.count(name)) {
+      std::cerr << "Unknown profile " << name << std::endl;
+      return EXIT_FAILURE;
+    }
+  }
+  std::map<std::string, Params> params_by_profile;
+  for (const auto& profile : profiles.cmdlines) {
+    if (!ParseCommandLine(profile.second, &params_by_profile[profile.first]))
+      return EXIT_FAILURE;
+  }
+  // Logging settings are not overridden by profiles.
+  const Params& params = params_by_profile[""];
+
+  if (dump_config) {
+    DumpConfig(params_by_profile, profiles);
+    return EXIT_SUCCESS;
   }
 
   net::ClientSocketPoolManager::set_max_sockets_per_pool(
@@ -506,6 +1302,7 @@ int main(int argc, char* argv[]) {
       kDefaultMaxSocketsPerGroup * kExpectedMaxUsers);
 
   CHECK(logging::InitLogging(params.log_settings));
+  net::SetHostLogMode(params.host_log_mode);
 
   if (!params.ssl_key_path.empty()) {
     net::SSLClientSocket::SetSSLKeyLogger(
@@ -532,57 +1329,36 @@ int main(int argc, char* argv[]) {
                          net::NetLogCaptureMode::kDefault);
   }
 
-  auto cert_context = net::BuildCertURLRequestContext(net_log);
-  scoped_refptr<net::CertNetFetcherURLRequest> cert_net_fetcher;
-#if defined(OS_LINUX) || defined(OS_MAC) || defined(OS_ANDROID)
-  cert_net_fetcher = base::MakeRefCounted<net::CertNetFetcherURLRequest>();
-  cert_net_fetcher->SetURLRequestContext(cert_context.get());
-#endif
-  auto context =
-      net::BuildURLRequestContext(params, std::move(cert_net_fetcher), net_log);
-  auto* session = context->http_transaction_factory()->GetSession();
-
-  auto listen_socket =
-      std::make_unique<net::TCPServerSocket>(net_log, net::NetLogSource());
-
-  int result = listen_socket->ListenWithAddressAndPort(
-      params.listen_addr, params.listen_port, kListenBackLog);
-  if (result != net::OK) {
-    LOG(ERROR) << "Failed to listen: " << result;
-    return EXIT_FAILURE;
+  // Hidden from --help, for catching leaks and crashes before releases.
+  if (proc.HasSwitch("self-test-soak")) {
+    return RunSoak(proc.GetSwitchValueASCII("self-test-soak"), params, net_log)
+               ? EXIT_SUCCESS
+               : EXIT_FAILURE;
+  }
+  // Also hidden, checks NAT64 address handling without a network.
+  if (proc.HasSwitch("self-test-nat64")) {
+    return net::RunNat64Test() ? EXIT_SUCCESS : EXIT_FAILURE;
   }
-  LOG(INFO) << "Listening on " << params.listen_addr << ":"
-            << params.listen_port;
-
-  std::unique_ptr<net::RedirectResolver> resolver;
-  if (params.protocol == net::ClientProtocol::kRedir) {
-    auto resolver_socket =
-        std::make_unique<net::UDPServerSocket>(net_log, net::NetLogSource());
-    resolver_socket->AllowAddressReuse();
-    net::IPAddress listen_addr;
-    if (!listen_addr.AssignFromIPLiteral(params.listen_addr)) {
-      LOG(ERROR) << "Failed to open resolver: " << net::ERR_ADDRESS_INVALID;
-      return EXIT_FAILURE;
-    }
 
-    result = resolver_socket->Listen(
-        net::IPEndPoint(listen_addr, params.listen_port));
+  ProfileSwitcher switcher(profiles, params_by_profile, net_log);
+  std::unique_ptr<net::AdminServer> admin_server;
+  if (params.admin_port != 0) {
+    auto admin_socket =
+        std::make_unique<net::TCPServerSocket>(net_log, net::NetLogSource());
+    int result = admin_socket->ListenWithAddressAndPort(
+        "127.0.0.1", params.admin_port, kListenBackLog);
     if (result != net::OK) {
-      LOG(ERROR) << "Failed to open resolver: " << result;
+      LOG(ERROR) << "Failed to listen for admin: " << result;
       return EXIT_FAILURE;
     }
-
-    resolver = std::make_unique<net::RedirectResolver>(
-        std::move(resolver_socket), params.resolver_range,
-        params.resolver_prefix);
+    LOG(INFO) << "Admin listening on 127.0.0.1:" << params.admin_port;
+    admin_server = std::make_unique<net::AdminServer>(
+        std::move(admin_socket),
+        base::BindRepeating(&ProfileSwitcher::active_profile,
+                            base::Unretained(&switcher)),
+        base::BindRepeating(&ProfileSwitcher::SwitchTo,
+                            base::Unretained(&switcher)));
   }
-
-  net::NaiveProxy naive_proxy(std::move(listen_socket), params.protocol,
-                              params.listen_user, params.listen_pass,
-                              params.concurrency, resolver.get(), session,
-                              kTrafficAnnotation);
-
-  base::RunLoop().Run();
-
-  return EXIT_SUCCESS;
+  switcher.Run();
+  return EXIT_FAILURE;
 }
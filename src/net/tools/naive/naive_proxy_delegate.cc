@@ -7,8 +7,10 @@
 
 #include "base/logging.h"
 #include "base/rand_util.h"
+#include "net/base/host_port_pair.h"
 #include "net/http/http_request_headers.h"
 #include "net/http/http_response_headers.h"
+#include "net/tools/naive/reverse_tunnel.h"
 #include "net/third_party/quiche/src/spdy/core/hpack/hpack_constants.h"
 
 namespace net {
@@ -44,8 +46,12 @@ void FillNonindexHeaderValue(uint64_t unique_bits, char* buf, int len) {
   }
 }
 
-NaiveProxyDelegate::NaiveProxyDelegate(const HttpRequestHeaders& extra_headers)
-    : extra_headers_(extra_headers) {
+NaiveProxyDelegate::NaiveProxyDelegate(const HttpRequestHeaders& extra_headers,
+                                       bool padding,
+                                       bool compression)
+    : extra_headers_(extra_headers),
+      padding_(padding),
+      compression_(compression) {
   InitializeNonindexCodes();
 }
 
@@ -53,19 +59,28 @@ NaiveProxyDelegate::~NaiveProxyDelegate() = default;
 
 void NaiveProxyDelegate::OnBeforeTunnelRequest(
     const ProxyServer& proxy_server,
+    const HostPortPair& endpoint,
     HttpRequestHeaders* extra_headers) {
   if (proxy_server.is_direct() || proxy_server.is_socks())
     return;
 
-  // Sends client-side padding header regardless of server support
-  std::string padding(base::RandInt(16, 32), '~');
-  FillNonindexHeaderValue(base::RandUint64(), &padding[0], padding.size());
-  extra_headers->SetHeader("padding", padding);
-
-  // Enables Fast Open in H2/H3 proxy client socket once the state of server
-  // padding support is known.
-  if (padding_state_by_server_[proxy_server] != PaddingSupport::kUnknown) {
-    extra_headers->SetHeader("fastopen", "1");
+  if (padding_) {
+    // Sends client-side padding header regardless of server support
+    std::string padding(base::RandInt(16, 32), '~');
+    FillNonindexHeaderValue(base::RandUint64(), &padding[0], padding.size());
+    extra_headers->SetHeader("padding", padding);
+
+    // Enables Fast Open in H2/H3 proxy client socket once the state of server
+    // padding support is known. Not with compression, which is decided by
+    // the response to each tunnel, nor for reverse tunnels, which are only
+    // answered once a connection arrives.
+    if (!compression_ && endpoint.host() != kReverseTunnelHost &&
+        padding_state_by_server_[proxy_server] != PaddingSupport::kUnknown) {
+      extra_headers->SetHeader("fastopen", "1");
+    }
+  }
+  if (compression_) {
+    extra_headers->SetHeader("compression", "deflate");
   }
   extra_headers->MergeFrom(extra_headers_);
 }
@@ -77,15 +92,18 @@ Error NaiveProxyDelegate::OnTunnelHeadersReceived(
     return OK;
 
   // Detects server padding support, even if it changes dynamically.
-  bool padding = response_headers.HasHeader("padding");
-  auto new_state =
-      padding ? PaddingSupport::kCapable : PaddingSupport::kIncapable;
-  auto& padding_state = padding_state_by_server_[proxy_server];
-  if (padding_state == PaddingSupport::kUnknown || padding_state != new_state) {
-    LOG(INFO) << "Padding capability of " << proxy_server.ToURI()
-              << (padding ? " detected" : " undetected");
+  if (padding_) {
+    bool padding = response_headers.HasHeader("padding");
+    auto new_state =
+        padding ? PaddingSupport::kCapable : PaddingSupport::kIncapable;
+    auto& padding_state = padding_state_by_server_[proxy_server];
+    if (padding_state == PaddingSupport::kUnknown ||
+        padding_state != new_state) {
+      LOG(INFO) << "Padding capability of " << proxy_server.ToURI()
+                << (padding ? " detected" : " undetected");
+    }
+    padding_state = new_state;
   }
-  padding_state = new_state;
   return OK;
 }
 
@@ -95,6 +113,9 @@ PaddingSupport NaiveProxyDelegate::GetProxyServerPaddingSupport(
   if (proxy_server.is_direct() || proxy_server.is_socks())
     return PaddingSupport::kIncapable;
 
+  if (!padding_)
+    return PaddingSupport::kIncapable;
+
   return padding_state_by_server_[proxy_server];
 }
 
@@ -142,6 +163,8 @@ PaddingSupport PaddingDetectorDelegate::GetClientPaddingSupport() {
     return PaddingSupport::kIncapable;
   } else if (client_protocol_ == ClientProtocol::kRedir) {
     return PaddingSupport::kIncapable;
+  } else if (client_protocol_ == ClientProtocol::kReverse) {
+    return PaddingSupport::kIncapable;
   }
 
   return detected_client_padding_support_;
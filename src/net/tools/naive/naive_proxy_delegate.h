@@ -34,7 +34,9 @@ enum class PaddingSupport {
 
 class NaiveProxyDelegate : public ProxyDelegate {
  public:
-  explicit NaiveProxyDelegate(const HttpRequestHeaders& extra_headers);
+  NaiveProxyDelegate(const HttpRequestHeaders& extra_headers,
+                     bool padding,
+                     bool compression);
   ~NaiveProxyDelegate() override;
 
   void OnResolveProxy(const GURL& url,
@@ -45,6 +47,7 @@ class NaiveProxyDelegate : public ProxyDelegate {
 
   // This only affects h2 proxy client socket.
   void OnBeforeTunnelRequest(const ProxyServer& proxy_server,
+                             const HostPortPair& endpoint,
                              HttpRequestHeaders* extra_headers) override;
 
   Error OnTunnelHeadersReceived(
@@ -55,6 +58,11 @@ class NaiveProxyDelegate : public ProxyDelegate {
 
  private:
   const HttpRequestHeaders& extra_headers_;
+  // Negotiates padding with proxy servers. Without it tunnels are plain
+  // CONNECT requests that any HTTP/2 forward proxy understands.
+  bool padding_;
+  // Asks proxy servers to compress tunnels.
+  bool compression_;
   std::map<ProxyServer, PaddingSupport> padding_state_by_server_;
 };
 
@@ -0,0 +1,173 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/nat64_detector.h"
+
+#include <algorithm>
+#include <cstdint>
+#include <iterator>
+#include <utility>
+
+#include "base/bind.h"
+#include "base/logging.h"
+#include "base/time/time.h"
+#include "net/base/address_list.h"
+#include "net/base/host_port_pair.h"
+#include "net/base/ip_endpoint.h"
+#include "net/base/net_errors.h"
+#include "net/base/network_isolation_key.h"
+#include "net/dns/public/dns_query_type.h"
+#include "net/log/net_log_with_source.h"
+
+namespace net {
+
+namespace {
+constexpr char kIpv4OnlyArpa[] = "ipv4only.arpa";
+constexpr int kRefreshIntervalMinutes = 10;
+constexpr size_t kWellKnownPrefixLength = 96;
+// Prefix lengths allowed by RFC 6052.
+constexpr size_t kPrefixLengths[] = {32, 40, 48, 56, 64, 96};
+
+// Bits 64 to 71 are reserved, so the IPv4 address skips over them.
+IPAddress EmbeddedIPv4(const IPAddress& address, size_t prefix_length) {
+  const auto& bytes = address.bytes();
+  uint8_t ipv4[4];
+  size_t i = prefix_length / 8;
+  for (uint8_t& byte : ipv4) {
+    if (i == 8)
+      ++i;
+    byte = bytes[i++];
+  }
+  return IPAddress(ipv4[0], ipv4[1], ipv4[2], ipv4[3]);
+}
+
+// 64:ff9b::
+IPAddress WellKnownPrefix() {
+  return IPAddress(0, 0x64, 0xff, 0x9b, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0);
+}
+
+// The addresses of ipv4only.arpa.
+bool IsWellKnownIPv4(const IPAddress& address) {
+  return address == IPAddress(192, 0, 0, 170) ||
+         address == IPAddress(192, 0, 0, 171);
+}
+
+bool MatchesAny(const std::vector<std::pair<IPAddress, size_t>>& prefixes,
+                const IPAddress& address,
+                IPAddress* ipv4) {
+  for (const auto& prefix : prefixes) {
+    if (IPAddressMatchesPrefix(address, prefix.first, prefix.second)) {
+      *ipv4 = EmbeddedIPv4(address, prefix.second);
+      return true;
+    }
+  }
+  return false;
+}
+}  // namespace
+
+Nat64Detector::Nat64Detector(HostResolver* host_resolver)
+    : host_resolver_(host_resolver) {
+  if (!host_resolver_)
+    return;
+  DoDetect();
+  refresh_timer_.Start(FROM_HERE,
+                       base::TimeDelta::FromMinutes(kRefreshIntervalMinutes),
+                       this, &Nat64Detector::DoDetect);
+}
+
+Nat64Detector::~Nat64Detector() = default;
+
+void Nat64Detector::AddPrefix(const IPAddress& prefix, size_t prefix_length) {
+  DCHECK(IsValidPrefix(prefix, prefix_length));
+  configured_prefixes_.emplace_back(prefix, prefix_length);
+}
+
+bool Nat64Detector::HasPrefix() const {
+  return !configured_prefixes_.empty() || !detected_prefixes_.empty();
+}
+
+bool Nat64Detector::ExtractIPv4(const IPAddress& address,
+                                IPAddress* ipv4) const {
+  if (!address.IsIPv6() || address.IsIPv4MappedIPv6())
+    return false;
+  if (IPAddressMatchesPrefix(address, WellKnownPrefix(),
+                             kWellKnownPrefixLength)) {
+    *ipv4 = EmbeddedIPv4(address, kWellKnownPrefixLength);
+    return true;
+  }
+  return MatchesAny(configured_prefixes_, address, ipv4) ||
+         MatchesAny(detected_prefixes_, address, ipv4);
+}
+
+// static
+bool Nat64Detector::IsValidPrefix(const IPAddress& prefix,
+                                  size_t prefix_length) {
+  return prefix.IsIPv6() &&
+         std::find(std::begin(kPrefixLengths), std::end(kPrefixLengths),
+                   prefix_length) != std::end(kPrefixLengths);
+}
+
+// static
+bool Nat64Detector::ParsePrefix(const IPAddress& address,
+                                IPAddress* prefix,
+                                size_t* prefix_length) {
+  if (!address.IsIPv6() || address.IsIPv4MappedIPv6())
+    return false;
+  for (size_t length : kPrefixLengths) {
+    if (length < 96 && address.bytes()[8] != 0)
+      continue;
+    if (!IsWellKnownIPv4(EmbeddedIPv4(address, length)))
+      continue;
+    std::vector<uint8_t> bytes(address.bytes().begin(),
+                               address.bytes().end());
+    std::fill(bytes.begin() + length / 8, bytes.end(), 0);
+    *prefix = IPAddress(bytes.data(), bytes.size());
+    *prefix_length = length;
+    return true;
+  }
+  return false;
+}
+
+void Nat64Detector::DoDetect() {
+  // Still waiting for the previous attempt.
+  if (request_)
+    return;
+  HostResolver::ResolveHostParameters parameters;
+  parameters.dns_query_type = DnsQueryType::AAAA;
+  request_ = host_resolver_->CreateRequest(
+      HostPortPair(kIpv4OnlyArpa, 0), NetworkIsolationKey(),
+      NetLogWithSource(), parameters);
+  int result = request_->Start(base::BindOnce(
+      &Nat64Detector::OnDetectComplete, base::Unretained(this)));
+  if (result != ERR_IO_PENDING)
+    OnDetectComplete(result);
+}
+
+void Nat64Detector::OnDetectComplete(int result) {
+  std::vector<std::pair<IPAddress, size_t>> prefixes;
+  if (result == OK && request_->GetAddressResults()) {
+    for (const auto& endpoint : request_->GetAddressResults().value()) {
+      std::pair<IPAddress, size_t> prefix;
+      if (!ParsePrefix(endpoint.address(), &prefix.first, &prefix.second))
+        continue;
+      if (std::find(prefixes.begin(), prefixes.end(), prefix) ==
+          prefixes.end()) {
+        prefixes.push_back(prefix);
+      }
+    }
+  }
+  request_.reset();
+
+  if (prefixes == detected_prefixes_)
+    return;
+  detected_prefixes_ = std::move(prefixes);
+  if (detected_prefixes_.empty())
+    LOG(INFO) << "No NAT64 prefix detected";
+  for (const auto& prefix : detected_prefixes_) {
+    LOG(INFO) << "NAT64 prefix " << prefix.first.ToString() << "/"
+              << prefix.second;
+  }
+}
+
+}  // namespace net
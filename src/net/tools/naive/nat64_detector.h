@@ -0,0 +1,68 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_NAT64_DETECTOR_H_
+#define NET_TOOLS_NAIVE_NAT64_DETECTOR_H_
+
+#include <cstddef>
+#include <memory>
+#include <utility>
+#include <vector>
+
+#include "base/macros.h"
+#include "base/timer/timer.h"
+#include "net/base/ip_address.h"
+#include "net/dns/host_resolver.h"
+
+namespace net {
+
+// Discovers the NAT64 prefixes of the local network by resolving
+// ipv4only.arpa (RFC 7050), and recovers the IPv4 addresses embedded in
+// IPv6 addresses synthesized by DNS64 (RFC 6052). The well-known prefix
+// 64:ff9b::/96 is always recognized.
+class Nat64Detector {
+ public:
+  // Only the well-known and added prefixes are used if |host_resolver| is
+  // null.
+  explicit Nat64Detector(HostResolver* host_resolver);
+  ~Nat64Detector();
+
+  // Adds a configured prefix, which must be valid.
+  void AddPrefix(const IPAddress& prefix, size_t prefix_length);
+
+  // Whether the network is known to have NAT64, i.e. a prefix was detected
+  // or added.
+  bool HasPrefix() const;
+
+  // Returns true and sets |ipv4| if |address| is in a NAT64 prefix.
+  bool ExtractIPv4(const IPAddress& address, IPAddress* ipv4) const;
+
+  // Whether |prefix| is IPv6 with a length allowed by RFC 6052.
+  static bool IsValidPrefix(const IPAddress& prefix, size_t prefix_length);
+
+  // Returns true and sets |prefix| and |prefix_length| if |address| is
+  // ipv4only.arpa synthesized by DNS64.
+  static bool ParsePrefix(const IPAddress& address,
+                          IPAddress* prefix,
+                          size_t* prefix_length);
+
+ private:
+  void DoDetect();
+  void OnDetectComplete(int result);
+
+  HostResolver* host_resolver_;
+  std::unique_ptr<HostResolver::ResolveHostRequest> request_;
+
+  // Pairs of prefix and prefix length in bits.
+  std::vector<std::pair<IPAddress, size_t>> configured_prefixes_;
+  std::vector<std::pair<IPAddress, size_t>> detected_prefixes_;
+
+  // The network may change its prefix, e.g. after roaming.
+  base::RepeatingTimer refresh_timer_;
+
+  DISALLOW_COPY_AND_ASSIGN(Nat64Detector);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_NAT64_DETECTOR_H_
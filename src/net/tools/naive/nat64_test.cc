@@ -0,0 +1,112 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#include "net/tools/naive/nat64_test.h"
+
+#include <cstddef>
+#include <iostream>
+
+#include "base/logging.h"
+#include "net/base/ip_address.h"
+#include "net/tools/naive/nat64_detector.h"
+
+namespace net {
+
+namespace {
+struct Nat64Case {
+  const char* prefix;
+  size_t prefix_length;
+  // ipv4only.arpa, i.e. 192.0.0.170, synthesized with the prefix.
+  const char* ipv4only_arpa;
+  // 192.0.2.33 synthesized with the prefix.
+  const char* synthesized;
+};
+
+constexpr Nat64Case kCases[] = {
+    {"2001:db8::", 32, "2001:db8:c000:aa::", "2001:db8:c000:221::"},
+    {"2001:db8:100::", 40, "2001:db8:1c0:0:aa::", "2001:db8:1c0:2:21::"},
+    {"2001:db8:122::", 48, "2001:db8:122:c000:0:aa00::",
+     "2001:db8:122:c000:2:2100::"},
+    {"2001:db8:122:300::", 56, "2001:db8:122:3c0:0:aa::",
+     "2001:db8:122:3c0:0:221::"},
+    {"2001:db8:122:344::", 64, "2001:db8:122:344:c0:0:aa00:0",
+     "2001:db8:122:344:c0:2:2100:0"},
+    {"2001:db8:122:344::", 96, "2001:db8:122:344::c000:aa",
+     "2001:db8:122:344::c000:221"},
+};
+
+IPAddress Parse(const char* literal) {
+  IPAddress address;
+  CHECK(address.AssignFromIPLiteral(literal));
+  return address;
+}
+
+bool CheckCase(const Nat64Case& c) {
+  const IPAddress expected_ipv4(192, 0, 2, 33);
+  const IPAddress expected_prefix = Parse(c.prefix);
+
+  IPAddress prefix;
+  size_t prefix_length = 0;
+  if (!Nat64Detector::ParsePrefix(Parse(c.ipv4only_arpa), &prefix,
+                                  &prefix_length) ||
+      prefix != expected_prefix || prefix_length != c.prefix_length) {
+    std::cerr << "Wrong prefix from " << c.ipv4only_arpa << ": "
+              << prefix.ToString() << "/" << prefix_length << std::endl;
+    return false;
+  }
+
+  Nat64Detector detector(/*host_resolver=*/nullptr);
+  IPAddress ipv4;
+  if (detector.HasPrefix() ||
+      detector.ExtractIPv4(Parse(c.synthesized), &ipv4)) {
+    std::cerr << "Unexpected prefix before adding " << c.prefix << "/"
+              << c.prefix_length << std::endl;
+    return false;
+  }
+  detector.AddPrefix(expected_prefix, c.prefix_length);
+  if (!detector.HasPrefix() ||
+      !detector.ExtractIPv4(Parse(c.synthesized), &ipv4) ||
+      ipv4 != expected_ipv4) {
+    std::cerr << "Wrong IPv4 from " << c.synthesized << ": "
+              << ipv4.ToString() << std::endl;
+    return false;
+  }
+  return true;
+}
+}  // namespace
+
+bool RunNat64Test() {
+  for (const auto& c : kCases) {
+    if (!CheckCase(c))
+      return false;
+  }
+
+  IPAddress prefix;
+  size_t prefix_length;
+  for (const char* literal :
+       {"2001:db8::1", "192.0.0.170", "::ffff:192.0.0.170"}) {
+    if (Nat64Detector::ParsePrefix(Parse(literal), &prefix, &prefix_length)) {
+      std::cerr << "Unexpected prefix from " << literal << std::endl;
+      return false;
+    }
+  }
+
+  // The well-known prefix needs no detection, but doesn't mean the network
+  // has NAT64.
+  Nat64Detector detector(/*host_resolver=*/nullptr);
+  IPAddress ipv4;
+  if (!detector.ExtractIPv4(Parse("64:ff9b::c000:221"), &ipv4) ||
+      ipv4 != IPAddress(192, 0, 2, 33) || detector.HasPrefix()) {
+    std::cerr << "Wrong IPv4 from the well-known prefix" << std::endl;
+    return false;
+  }
+  if (detector.ExtractIPv4(Parse("::ffff:192.0.2.33"), &ipv4)) {
+    std::cerr << "Unexpected IPv4 from a mapped address" << std::endl;
+    return false;
+  }
+
+  std::cout << "NAT64 test passed" << std::endl;
+  return true;
+}
+
+}  // namespace net
@@ -0,0 +1,17 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#ifndef NET_TOOLS_NAIVE_NAT64_TEST_H_
+#define NET_TOOLS_NAIVE_NAT64_TEST_H_
+
+namespace net {
+
+// Checks NAT64 prefix discovery from ipv4only.arpa answers and recovery of
+// IPv4 addresses from synthesized ones, with the examples of RFC 6052
+// section 2.4 for every prefix length.
+//
+// Returns false and prints the failed case on mismatch.
+bool RunNat64Test();
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_NAT64_TEST_H_
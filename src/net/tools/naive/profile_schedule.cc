@@ -0,0 +1,183 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/profile_schedule.h"
+
+#include <algorithm>
+#include <set>
+#include <utility>
+
+#include "base/strings/string_number_conversions.h"
+#include "base/strings/string_piece.h"
+#include "base/strings/stringprintf.h"
+
+namespace net {
+
+namespace {
+constexpr int kMinutesPerDay = 24 * 60;
+constexpr int kEveryDay = 0x7f;
+constexpr int kDaysPerWeek = 7;
+constexpr const char* kDayNames[kDaysPerWeek] = {"sun", "mon", "tue", "wed",
+                                                 "thu", "fri", "sat"};
+
+// Parses "HH:MM" into minutes since midnight. "24:00" is allowed.
+bool ParseTimeOfDay(base::StringPiece str, int* minutes) {
+  if (str.size() != 5 || str[2] != ':')
+    return false;
+  int hour;
+  int minute;
+  if (!base::StringToInt(str.substr(0, 2), &hour) ||
+      !base::StringToInt(str.substr(3, 2), &minute)) {
+    return false;
+  }
+  if (hour < 0 || minute < 0 || minute >= 60)
+    return false;
+  *minutes = hour * 60 + minute;
+  return *minutes <= kMinutesPerDay;
+}
+
+std::string TimeOfDayToString(int minutes) {
+  return base::StringPrintf("%02d:%02d", minutes / 60, minutes % 60);
+}
+
+base::Time LocalMidnight(base::Time time) {
+  base::Time::Exploded exploded;
+  time.LocalExplode(&exploded);
+  exploded.hour = 0;
+  exploded.minute = 0;
+  exploded.second = 0;
+  exploded.millisecond = 0;
+  base::Time midnight;
+  if (!base::Time::FromLocalExploded(exploded, &midnight))
+    return time;
+  return midnight;
+}
+}  // namespace
+
+ProfileSchedule::ProfileSchedule() = default;
+
+ProfileSchedule::~ProfileSchedule() = default;
+
+bool ProfileSchedule::Parse(const base::Value& value, std::string* error) {
+  if (!value.is_list()) {
+    *error = "Schedule is not a list";
+    return false;
+  }
+  for (const auto& item : value.GetList()) {
+    if (!item.is_dict()) {
+      *error = "Schedule entry is not a dictionary";
+      return false;
+    }
+    Entry entry;
+    const auto* profile = item.FindStringKey("profile");
+    if (!profile || profile->empty()) {
+      *error = "Schedule entry without profile";
+      return false;
+    }
+    entry.profile = *profile;
+
+    entry.days = kEveryDay;
+    const auto* days = item.FindListKey("days");
+    if (days) {
+      entry.days = 0;
+      for (const auto& day : days->GetList()) {
+        const char* const* it = day.is_string()
+                                    ? std::find(std::begin(kDayNames),
+                                                std::end(kDayNames),
+                                                day.GetString())
+                                    : std::end(kDayNames);
+        if (it == std::end(kDayNames)) {
+          *error = "Invalid day in schedule";
+          return false;
+        }
+        entry.days |= 1 << (it - std::begin(kDayNames));
+      }
+    }
+
+    const auto* from = item.FindStringKey("from");
+    const auto* to = item.FindStringKey("to");
+    if (!from || !to || !ParseTimeOfDay(*from, &entry.begin) ||
+        !ParseTimeOfDay(*to, &entry.end) || entry.begin == entry.end) {
+      *error = "Invalid time range in schedule";
+      return false;
+    }
+    entries_.push_back(entry);
+  }
+  return true;
+}
+
+base::Value ProfileSchedule::ToValue() const {
+  base::Value list(base::Value::Type::LIST);
+  for (const auto& entry : entries_) {
+    base::Value item(base::Value::Type::DICTIONARY);
+    item.SetStringKey("profile", entry.profile);
+    base::Value days(base::Value::Type::LIST);
+    for (int i = 0; i < kDaysPerWeek; ++i) {
+      if (entry.days & (1 << i))
+        days.Append(kDayNames[i]);
+    }
+    item.SetKey("days", std::move(days));
+    item.SetStringKey("from", TimeOfDayToString(entry.begin));
+    item.SetStringKey("to", TimeOfDayToString(entry.end));
+    list.Append(std::move(item));
+  }
+  return list;
+}
+
+std::vector<std::string> ProfileSchedule::GetProfiles() const {
+  std::vector<std::string> profiles;
+  for (const auto& entry : entries_)
+    profiles.push_back(entry.profile);
+  return profiles;
+}
+
+std::string ProfileSchedule::GetActiveProfile(
+    base::Time time,
+    const std::string& default_profile) const {
+  base::Time::Exploded exploded;
+  time.LocalExplode(&exploded);
+  int day = exploded.day_of_week;
+  int previous_day = (day + kDaysPerWeek - 1) % kDaysPerWeek;
+  int minute = exploded.hour * 60 + exploded.minute;
+  for (const auto& entry : entries_) {
+    bool covered;
+    if (entry.begin < entry.end) {
+      covered = (entry.days & (1 << day)) && entry.begin <= minute &&
+                minute < entry.end;
+    } else {
+      covered = ((entry.days & (1 << day)) && minute >= entry.begin) ||
+                ((entry.days & (1 << previous_day)) && minute < entry.end);
+    }
+    if (covered)
+      return entry.profile;
+  }
+  return default_profile;
+}
+
+base::Time ProfileSchedule::GetNextSwitch(
+    base::Time time,
+    const std::string& default_profile) const {
+  std::set<int> boundaries;
+  for (const auto& entry : entries_) {
+    boundaries.insert(entry.begin);
+    boundaries.insert(entry.end);
+  }
+  std::string profile = GetActiveProfile(time, default_profile);
+  // Checks one day past a week for windows ending on the next day.
+  for (int i = 0; i <= kDaysPerWeek; ++i) {
+    // Starts from noon to stay clear of DST transitions.
+    base::Time midnight = LocalMidnight(
+        LocalMidnight(time) + base::TimeDelta::FromHours(i * 24 + 12));
+    for (int boundary : boundaries) {
+      base::Time next = midnight + base::TimeDelta::FromMinutes(boundary);
+      if (next <= time)
+        continue;
+      if (GetActiveProfile(next, default_profile) != profile)
+        return next;
+    }
+  }
+  return base::Time();
+}
+
+}  // namespace net
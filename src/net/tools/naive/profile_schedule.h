@@ -0,0 +1,66 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_PROFILE_SCHEDULE_H_
+#define NET_TOOLS_NAIVE_PROFILE_SCHEDULE_H_
+
+#include <string>
+#include <vector>
+
+#include "base/macros.h"
+#include "base/time/time.h"
+#include "base/values.h"
+
+namespace net {
+
+// Picks the active configuration profile by weekday and time of day in
+// local time.
+class ProfileSchedule {
+ public:
+  ProfileSchedule();
+  ~ProfileSchedule();
+
+  // Parses a list of entries like
+  //   {"profile": "work", "days": ["mon", "fri"],
+  //    "from": "09:00", "to": "18:00"}.
+  // "days" defaults to every day. An entry whose "from" is later than its
+  // "to" ends on the next day.
+  bool Parse(const base::Value& value, std::string* error);
+
+  bool empty() const { return entries_.empty(); }
+
+  // Returns the entries in the format Parse() takes, with "days" always
+  // spelled out.
+  base::Value ToValue() const;
+
+  // Returns the names of the profiles in the schedule.
+  std::vector<std::string> GetProfiles() const;
+
+  // Returns the profile of the first entry covering |time|, or
+  // |default_profile| if none does.
+  std::string GetActiveProfile(base::Time time,
+                               const std::string& default_profile) const;
+
+  // Returns the first time after |time| when the active profile changes, or
+  // a null time if it does not change within a week.
+  base::Time GetNextSwitch(base::Time time,
+                           const std::string& default_profile) const;
+
+ private:
+  struct Entry {
+    std::string profile;
+    // Bit 0 is Sunday.
+    int days;
+    // Minutes since midnight.
+    int begin;
+    int end;
+  };
+
+  std::vector<Entry> entries_;
+
+  DISALLOW_COPY_AND_ASSIGN(ProfileSchedule);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_PROFILE_SCHEDULE_H_
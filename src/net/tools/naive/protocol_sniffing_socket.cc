@@ -0,0 +1,376 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/protocol_sniffing_socket.h"
+
+#include <algorithm>
+#include <cstring>
+#include <utility>
+
+#include "base/bind.h"
+#include "base/callback_helpers.h"
+#include "base/logging.h"
+#include "build/build_config.h"
+#include "net/base/net_errors.h"
+
+#if defined(OS_LINUX)
+#include <linux/netfilter_ipv4.h>
+#include <linux/netfilter_ipv6/ip6_tables.h>
+#include <netinet/in.h>
+#include <sys/socket.h>
+
+#include "net/base/ip_address.h"
+#include "net/base/sockaddr_storage.h"
+#include "net/socket/tcp_client_socket.h"
+#endif
+
+namespace net {
+
+namespace {
+constexpr int kBufferSize = 64 * 1024;
+constexpr char kSocks5Version = '\x05';
+constexpr char kTlsHandshake = '\x16';
+constexpr char kConnectMethod[] = "CONNECT ";
+constexpr size_t kConnectMethodSize = sizeof(kConnectMethod) - 1;
+
+enum class Detection {
+  kDetected,
+  kNeedMoreBytes,
+  // Serving TLS needs a certificate, which listeners don't have.
+  kTls,
+  kUnknown,
+};
+
+Detection DetectProtocol(const std::string& buffer, ClientProtocol* protocol) {
+  DCHECK(!buffer.empty());
+  if (buffer[0] == kSocks5Version) {
+    *protocol = ClientProtocol::kSocks5;
+    return Detection::kDetected;
+  }
+  size_t size = std::min(buffer.size(), kConnectMethodSize);
+  if (buffer.compare(0, size, kConnectMethod, size) == 0) {
+    if (size < kConnectMethodSize)
+      return Detection::kNeedMoreBytes;
+    *protocol = ClientProtocol::kHttp;
+    return Detection::kDetected;
+  }
+  if (buffer[0] == kTlsHandshake)
+    return Detection::kTls;
+  return Detection::kUnknown;
+}
+
+#if defined(OS_LINUX)
+// Returns true if iptables redirected the connection here, i.e. its original
+// destination is not the listener itself.
+bool IsRedirected(const StreamSocket* transport_socket) {
+  const auto* socket = static_cast<const TCPClientSocket*>(transport_socket);
+  IPEndPoint local_ipe;
+  if (socket->GetLocalAddress(&local_ipe) != OK)
+    return false;
+  int sd = socket->SocketDescriptorForTesting();
+  SockaddrStorage dst;
+  int rv;
+  if (local_ipe.address().IsIPv4MappedIPv6()) {
+    local_ipe = IPEndPoint(ConvertIPv4MappedIPv6ToIPv4(local_ipe.address()),
+                           local_ipe.port());
+    rv = getsockopt(sd, SOL_IP, SO_ORIGINAL_DST, dst.addr, &dst.addr_len);
+  } else if (local_ipe.address().IsIPv6()) {
+    rv = getsockopt(sd, SOL_IPV6, IP6T_SO_ORIGINAL_DST, dst.addr,
+                    &dst.addr_len);
+  } else {
+    rv = getsockopt(sd, SOL_IP, SO_ORIGINAL_DST, dst.addr, &dst.addr_len);
+  }
+  // Fails without a conntrack entry, e.g. if there are no nat rules at all.
+  IPEndPoint ipe;
+  if (rv != 0 || !ipe.FromSockAddr(dst.addr, dst.addr_len))
+    return false;
+  return ipe != local_ipe;
+}
+#endif
+}  // namespace
+
+ProtocolSniffingSocket::ProtocolSniffingSocket(
+    std::unique_ptr<StreamSocket> transport_socket)
+    : transport_(std::move(transport_socket)),
+      next_state_(STATE_NONE),
+      completed_sniff_(false),
+      was_ever_used_(false),
+      protocol_(ClientProtocol::kRedir) {}
+
+ProtocolSniffingSocket::~ProtocolSniffingSocket() {
+  Disconnect();
+}
+
+ClientProtocol ProtocolSniffingSocket::protocol() const {
+  DCHECK(completed_sniff_);
+  return protocol_;
+}
+
+int ProtocolSniffingSocket::Connect(CompletionOnceCallback callback) {
+  DCHECK(transport_);
+  DCHECK_EQ(STATE_NONE, next_state_);
+  DCHECK(!user_callback_);
+
+  // If already connected, then just return OK.
+  if (completed_sniff_)
+    return OK;
+
+#if defined(OS_LINUX)
+  // Redirected traffic is not read from, as the server may speak first.
+  if (IsRedirected(transport_.get())) {
+    protocol_ = ClientProtocol::kRedir;
+    completed_sniff_ = true;
+    return OK;
+  }
+#endif
+
+  next_state_ = STATE_SNIFF_READ;
+  buffer_.clear();
+
+  int rv = DoLoop(OK);
+  if (rv == ERR_IO_PENDING) {
+    user_callback_ = std::move(callback);
+  }
+  return rv;
+}
+
+void ProtocolSniffingSocket::Disconnect() {
+  completed_sniff_ = false;
+  transport_->Disconnect();
+
+  // Reset other states to make sure they aren't mistakenly used later.
+  // These are the states initialized by Connect().
+  next_state_ = STATE_NONE;
+  user_callback_.Reset();
+}
+
+bool ProtocolSniffingSocket::IsConnected() const {
+  return completed_sniff_ && transport_->IsConnected();
+}
+
+bool ProtocolSniffingSocket::IsConnectedAndIdle() const {
+  return completed_sniff_ && buffer_.empty() &&
+         transport_->IsConnectedAndIdle();
+}
+
+const NetLogWithSource& ProtocolSniffingSocket::NetLog() const {
+  return transport_->NetLog();
+}
+
+bool ProtocolSniffingSocket::WasEverUsed() const {
+  return was_ever_used_;
+}
+
+bool ProtocolSniffingSocket::WasAlpnNegotiated() const {
+  return transport_->WasAlpnNegotiated();
+}
+
+NextProto ProtocolSniffingSocket::GetNegotiatedProtocol() const {
+  return transport_->GetNegotiatedProtocol();
+}
+
+bool ProtocolSniffingSocket::GetSSLInfo(SSLInfo* ssl_info) {
+  return transport_->GetSSLInfo(ssl_info);
+}
+
+void ProtocolSniffingSocket::GetConnectionAttempts(
+    ConnectionAttempts* out) const {
+  out->clear();
+}
+
+int64_t ProtocolSniffingSocket::GetTotalReceivedBytes() const {
+  return transport_->GetTotalReceivedBytes();
+}
+
+void ProtocolSniffingSocket::ApplySocketTag(const SocketTag& tag) {
+  return transport_->ApplySocketTag(tag);
+}
+
+void ProtocolSniffingSocket::DoCallback(int result) {
+  DCHECK_NE(ERR_IO_PENDING, result);
+  DCHECK(user_callback_);
+
+  // Since Run() may result in Read being called,
+  // clear user_callback_ up front.
+  std::move(user_callback_).Run(result);
+}
+
+void ProtocolSniffingSocket::OnIOComplete(int result) {
+  DCHECK_NE(STATE_NONE, next_state_);
+  // ReadIfReady() completes with OK once the socket becomes readable, and the
+  // read has to be issued again.
+  if (result == OK)
+    next_state_ = STATE_SNIFF_READ;
+  int rv = DoLoop(result);
+  if (rv != ERR_IO_PENDING) {
+    DoCallback(rv);
+  }
+}
+
+void ProtocolSniffingSocket::OnReadWriteComplete(
+    CompletionOnceCallback callback,
+    int result) {
+  DCHECK_NE(ERR_IO_PENDING, result);
+  DCHECK(callback);
+
+  if (result > 0)
+    was_ever_used_ = true;
+  std::move(callback).Run(result);
+}
+
+int ProtocolSniffingSocket::DoLoop(int last_io_result) {
+  DCHECK_NE(next_state_, STATE_NONE);
+  int rv = last_io_result;
+  do {
+    State state = next_state_;
+    next_state_ = STATE_NONE;
+    switch (state) {
+      case STATE_SNIFF_READ:
+        DCHECK_EQ(OK, rv);
+        rv = DoSniffRead();
+        break;
+      case STATE_SNIFF_READ_COMPLETE:
+        rv = DoSniffReadComplete(rv);
+        break;
+      default:
+        NOTREACHED() << "bad state";
+        rv = ERR_UNEXPECTED;
+        break;
+    }
+  } while (rv != ERR_IO_PENDING && next_state_ != STATE_NONE);
+  return rv;
+}
+
+int ProtocolSniffingSocket::DoSniffRead() {
+  next_state_ = STATE_SNIFF_READ_COMPLETE;
+
+  sniff_buf_ = base::MakeRefCounted<IOBuffer>(kBufferSize);
+  return transport_->ReadIfReady(
+      sniff_buf_.get(), kBufferSize,
+      base::BindOnce(&ProtocolSniffingSocket::OnIOComplete,
+                     base::Unretained(this)));
+}
+
+int ProtocolSniffingSocket::DoSniffReadComplete(int result) {
+  if (result < 0)
+    return result;
+
+  if (result == 0) {
+    return ERR_CONNECTION_CLOSED;
+  }
+
+  buffer_.append(sniff_buf_->data(), result);
+  sniff_buf_ = nullptr;
+
+  switch (DetectProtocol(buffer_, &protocol_)) {
+    case Detection::kDetected:
+      break;
+    case Detection::kNeedMoreBytes:
+      next_state_ = STATE_SNIFF_READ;
+      return OK;
+    case Detection::kTls:
+      LOG(WARNING) << "TLS clients are not supported, use socks or http";
+      return ERR_NOT_IMPLEMENTED;
+    case Detection::kUnknown:
+      return ERR_INVALID_ARGUMENT;
+  }
+
+  completed_sniff_ = true;
+  next_state_ = STATE_NONE;
+  return OK;
+}
+
+// Read is called by the transport layer above to read. This can only be done
+// if the protocol detection is complete.
+int ProtocolSniffingSocket::Read(IOBuffer* buf,
+                                 int buf_len,
+                                 CompletionOnceCallback callback) {
+  DCHECK(completed_sniff_);
+  DCHECK_EQ(STATE_NONE, next_state_);
+  DCHECK(!user_callback_);
+  DCHECK(callback);
+
+  if (!buffer_.empty()) {
+    was_ever_used_ = true;
+    int data_len = buffer_.size();
+    if (data_len <= buf_len) {
+      std::memcpy(buf->data(), buffer_.data(), data_len);
+      buffer_.clear();
+      return data_len;
+    } else {
+      std::memcpy(buf->data(), buffer_.data(), buf_len);
+      buffer_ = buffer_.substr(buf_len);
+      return buf_len;
+    }
+  }
+
+  int rv = transport_->Read(
+      buf, buf_len,
+      base::BindOnce(&ProtocolSniffingSocket::OnReadWriteComplete,
+                     base::Unretained(this), std::move(callback)));
+  if (rv > 0)
+    was_ever_used_ = true;
+  return rv;
+}
+
+int ProtocolSniffingSocket::ReadIfReady(IOBuffer* buf,
+                                        int buf_len,
+                                        CompletionOnceCallback callback) {
+  DCHECK(completed_sniff_);
+  DCHECK_EQ(STATE_NONE, next_state_);
+  DCHECK(!user_callback_);
+  DCHECK(callback);
+
+  if (!buffer_.empty())
+    return Read(buf, buf_len, std::move(callback));
+
+  int rv = transport_->ReadIfReady(buf, buf_len, std::move(callback));
+  if (rv > 0)
+    was_ever_used_ = true;
+  return rv;
+}
+
+int ProtocolSniffingSocket::CancelReadIfReady() {
+  return transport_->CancelReadIfReady();
+}
+
+// Write is called by the transport layer. This can only be done if the
+// protocol detection is complete.
+int ProtocolSniffingSocket::Write(
+    IOBuffer* buf,
+    int buf_len,
+    CompletionOnceCallback callback,
+    const NetworkTrafficAnnotationTag& traffic_annotation) {
+  DCHECK(completed_sniff_);
+  DCHECK_EQ(STATE_NONE, next_state_);
+  DCHECK(!user_callback_);
+  DCHECK(callback);
+
+  int rv = transport_->Write(
+      buf, buf_len,
+      base::BindOnce(&ProtocolSniffingSocket::OnReadWriteComplete,
+                     base::Unretained(this), std::move(callback)),
+      traffic_annotation);
+  if (rv > 0)
+    was_ever_used_ = true;
+  return rv;
+}
+
+int ProtocolSniffingSocket::SetReceiveBufferSize(int32_t size) {
+  return transport_->SetReceiveBufferSize(size);
+}
+
+int ProtocolSniffingSocket::SetSendBufferSize(int32_t size) {
+  return transport_->SetSendBufferSize(size);
+}
+
+int ProtocolSniffingSocket::GetPeerAddress(IPEndPoint* address) const {
+  return transport_->GetPeerAddress(address);
+}
+
+int ProtocolSniffingSocket::GetLocalAddress(IPEndPoint* address) const {
+  return transport_->GetLocalAddress(address);
+}
+
+}  // namespace net
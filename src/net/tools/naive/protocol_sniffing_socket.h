@@ -0,0 +1,118 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_PROTOCOL_SNIFFING_SOCKET_H_
+#define NET_TOOLS_NAIVE_PROTOCOL_SNIFFING_SOCKET_H_
+
+#include <cstdint>
+#include <memory>
+#include <string>
+
+#include "base/macros.h"
+#include "base/memory/scoped_refptr.h"
+#include "net/base/completion_once_callback.h"
+#include "net/base/io_buffer.h"
+#include "net/base/ip_endpoint.h"
+#include "net/log/net_log_with_source.h"
+#include "net/socket/connection_attempts.h"
+#include "net/socket/next_proto.h"
+#include "net/socket/stream_socket.h"
+#include "net/ssl/ssl_info.h"
+#include "net/tools/naive/naive_protocol.h"
+
+namespace net {
+
+// This StreamSocket detects which protocol a local client speaks from the
+// first bytes it sends, then replays those bytes to the layer above.
+class ProtocolSniffingSocket : public StreamSocket {
+ public:
+  explicit ProtocolSniffingSocket(
+      std::unique_ptr<StreamSocket> transport_socket);
+
+  // On destruction Disconnect() is called.
+  ~ProtocolSniffingSocket() override;
+
+  // Valid after Connect() succeeds. kRedir means the connection was
+  // redirected by iptables, which is only detected on Linux. Connect() fails
+  // if a client connecting directly speaks neither SOCKS5 nor HTTP CONNECT,
+  // with ERR_NOT_IMPLEMENTED if it speaks TLS.
+  ClientProtocol protocol() const;
+
+  // Returns the accepted socket, e.g. for looking up redirect information.
+  StreamSocket* transport_socket() const { return transport_.get(); }
+
+  // StreamSocket implementation.
+
+  // Reads until the protocol is known.
+  int Connect(CompletionOnceCallback callback) override;
+  void Disconnect() override;
+  bool IsConnected() const override;
+  bool IsConnectedAndIdle() const override;
+  const NetLogWithSource& NetLog() const override;
+  bool WasEverUsed() const override;
+  bool WasAlpnNegotiated() const override;
+  NextProto GetNegotiatedProtocol() const override;
+  bool GetSSLInfo(SSLInfo* ssl_info) override;
+  void GetConnectionAttempts(ConnectionAttempts* out) const override;
+  void ClearConnectionAttempts() override {}
+  void AddConnectionAttempts(const ConnectionAttempts& attempts) override {}
+  int64_t GetTotalReceivedBytes() const override;
+  void ApplySocketTag(const SocketTag& tag) override;
+
+  // Socket implementation.
+  int Read(IOBuffer* buf,
+           int buf_len,
+           CompletionOnceCallback callback) override;
+  int ReadIfReady(IOBuffer* buf,
+                  int buf_len,
+                  CompletionOnceCallback callback) override;
+  int CancelReadIfReady() override;
+  int Write(IOBuffer* buf,
+            int buf_len,
+            CompletionOnceCallback callback,
+            const NetworkTrafficAnnotationTag& traffic_annotation) override;
+
+  int SetReceiveBufferSize(int32_t size) override;
+  int SetSendBufferSize(int32_t size) override;
+
+  int GetPeerAddress(IPEndPoint* address) const override;
+  int GetLocalAddress(IPEndPoint* address) const override;
+
+ private:
+  enum State {
+    STATE_SNIFF_READ,
+    STATE_SNIFF_READ_COMPLETE,
+    STATE_NONE,
+  };
+
+  void DoCallback(int result);
+  void OnIOComplete(int result);
+  void OnReadWriteComplete(CompletionOnceCallback callback, int result);
+
+  int DoLoop(int last_io_result);
+  int DoSniffRead();
+  int DoSniffReadComplete(int result);
+
+  // Stores the underlying socket.
+  std::unique_ptr<StreamSocket> transport_;
+
+  State next_state_;
+
+  // Stores the callback to the layer above, called on completing Connect().
+  CompletionOnceCallback user_callback_;
+
+  scoped_refptr<IOBuffer> sniff_buf_;
+
+  // Bytes read during detection, replayed by Read().
+  std::string buffer_;
+  bool completed_sniff_;
+  bool was_ever_used_;
+
+  ClientProtocol protocol_;
+
+  DISALLOW_COPY_AND_ASSIGN(ProtocolSniffingSocket);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_PROTOCOL_SNIFFING_SOCKET_H_
@@ -17,6 +17,8 @@
 #include "net/dns/dns_response.h"
 #include "net/dns/dns_util.h"
 #include "net/socket/datagram_server_socket.h"
+#include "net/tools/naive/host_log.h"
+#include "net/tools/naive/nat64_detector.h"
 
 namespace {
 constexpr int kUdpReadBufferSize = 1024;
@@ -36,10 +38,12 @@ Resolution::~Resolution() = default;
 
 RedirectResolver::RedirectResolver(std::unique_ptr<DatagramServerSocket> socket,
                                    const IPAddress& range,
-                                   size_t prefix)
+                                   size_t prefix,
+                                   const Nat64Detector* nat64_detector)
     : socket_(std::move(socket)),
       range_(range),
       prefix_(prefix),
+      nat64_detector_(nat64_detector),
       offset_(0),
       buffer_(base::MakeRefCounted<IOBufferWithSize>(kUdpReadBufferSize)) {
   DCHECK(socket_);
@@ -52,6 +56,11 @@ RedirectResolver::RedirectResolver(std::unique_ptr<DatagramServerSocket> socket,
 
 RedirectResolver::~RedirectResolver() = default;
 
+void RedirectResolver::Close() {
+  weak_ptr_factory_.InvalidateWeakPtrs();
+  socket_.reset();
+}
+
 void RedirectResolver::DoRead() {
   for (;;) {
     int rv = socket_->RecvFrom(
@@ -144,9 +153,9 @@ int RedirectResolver::HandleReadResult(int result) {
         // Too few available addresses. Overwrites old one.
         auto res_it = by_addr->second;
 
-        LOG(INFO) << "Overwrite " << res_it->name << " "
-                  << PackedIPv4ToString(res_it->addr) << " with " << name << " "
-                  << PackedIPv4ToString(addr);
+        LOG(INFO) << "Overwrite " << HostForLog(res_it->name) << " "
+                  << PackedIPv4ToString(res_it->addr) << " with "
+                  << HostForLog(name) << " " << PackedIPv4ToString(addr);
         resolution_by_name_.erase(res_it->by_name);
         resolutions_.erase(res_it);
         resolutions_.emplace_back();
@@ -160,7 +169,8 @@ int RedirectResolver::HandleReadResult(int result) {
         res_it->by_name = by_name;
         res_it->by_addr = by_addr;
       } else {
-        LOG(INFO) << "Add " << name << " " << PackedIPv4ToString(addr);
+        LOG(INFO) << "Add " << HostForLog(name) << " "
+                  << PackedIPv4ToString(addr);
         resolutions_.emplace_back();
         auto res_it = std::prev(resolutions_.end());
 
@@ -178,7 +188,7 @@ int RedirectResolver::HandleReadResult(int result) {
              it != resolutions_.end() &&
              (now - it->time).InSeconds() > kResolutionRecycleTime;) {
           auto next = std::next(it);
-          LOG(INFO) << "Drop " << it->name << " "
+          LOG(INFO) << "Drop " << HostForLog(it->name) << " "
                     << PackedIPv4ToString(it->addr);
           resolution_by_name_.erase(it->by_name);
           resolution_by_addr_.erase(it->by_addr);
@@ -208,11 +218,17 @@ int RedirectResolver::HandleReadResult(int result) {
     }
     std::memcpy(buffer_->data(), response.io_buffer()->data(), size);
   } else {
+    // An empty AAAA answer lets DNS64 synthesize from the A record above.
+    // Without NAT64 it would only hide the failure from clients.
+    uint8_t rcode = query.qtype() == dns_protocol::kTypeAAAA &&
+                            nat64_detector_ && nat64_detector_->HasPrefix()
+                        ? dns_protocol::kRcodeNOERROR
+                        : dns_protocol::kRcodeSERVFAIL;
     base::Optional<DnsQuery> query_opt(base::in_place, query.id(),
                                        query.qname(), query.qtype());
     DnsResponse response(query.id(), /*is_authoritative=*/false, /*answers=*/{},
                          /*authority_records=*/{}, /*additional_records=*/{},
-                         query_opt, dns_protocol::kRcodeSERVFAIL);
+                         query_opt, rcode);
     size = response.io_buffer_size();
     if (size > buffer_->size() || !response.io_buffer()) {
       return ERR_NO_BUFFER_SPACE;
@@ -22,6 +22,7 @@ namespace net {
 
 class DatagramServerSocket;
 class IOBufferWithSize;
+class Nat64Detector;
 
 struct Resolution {
   Resolution();
@@ -36,11 +37,18 @@ struct Resolution {
 
 class RedirectResolver {
  public:
+  // AAAA queries only get empty answers for DNS64 if |nat64_detector| knows
+  // a NAT64 prefix.
   RedirectResolver(std::unique_ptr<DatagramServerSocket> socket,
                    const IPAddress& range,
-                   size_t prefix);
+                   size_t prefix,
+                   const Nat64Detector* nat64_detector);
   ~RedirectResolver();
 
+  // Stops answering queries and frees the port. Names resolved so far can
+  // still be looked up.
+  void Close();
+
   bool IsInResolvedRange(const IPAddress& address) const;
   std::string FindNameByAddress(const IPAddress& address) const;
 
@@ -53,6 +61,7 @@ class RedirectResolver {
   std::unique_ptr<DatagramServerSocket> socket_;
   IPAddress range_;
   size_t prefix_;
+  const Nat64Detector* nat64_detector_;
   uint32_t offset_;
   scoped_refptr<IOBufferWithSize> buffer_;
   IPEndPoint recv_address_;
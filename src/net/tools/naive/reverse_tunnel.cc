@@ -0,0 +1,176 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#include "net/tools/naive/reverse_tunnel.h"
+
+#include <limits>
+#include <utility>
+
+#include "base/bind.h"
+#include "base/logging.h"
+#include "base/strings/string_number_conversions.h"
+#include "base/strings/string_split.h"
+#include "base/strings/strcat.h"
+#include "net/base/ip_endpoint.h"
+#include "net/base/net_errors.h"
+#include "net/socket/stream_socket.h"
+#include "net/socket/tcp_server_socket.h"
+
+namespace net {
+namespace {
+constexpr int kListenBackLog = 64;
+}  // namespace
+
+std::string ReverseForward::ToString() const {
+  return base::StrCat(
+      {base::NumberToString(remote_port), ":", target.ToString()});
+}
+
+bool ParseReverseForwards(base::StringPiece spec,
+                          std::vector<ReverseForward>* forwards) {
+  for (base::StringPiece item : base::SplitStringPiece(
+           spec, ",", base::TRIM_WHITESPACE, base::SPLIT_WANT_NONEMPTY)) {
+    auto colon = item.find(':');
+    if (colon == base::StringPiece::npos)
+      return false;
+    int remote_port;
+    if (!base::StringToInt(item.substr(0, colon), &remote_port) ||
+        remote_port <= 0 ||
+        remote_port > std::numeric_limits<uint16_t>::max()) {
+      return false;
+    }
+    ReverseForward forward;
+    forward.remote_port = static_cast<uint16_t>(remote_port);
+    forward.target =
+        HostPortPair::FromString(std::string(item.substr(colon + 1)));
+    if (forward.target.host().empty() || forward.target.port() == 0)
+      return false;
+    forwards->push_back(forward);
+  }
+  return true;
+}
+
+bool ParseReversePorts(base::StringPiece spec, std::set<uint16_t>* ports) {
+  for (base::StringPiece item : base::SplitStringPiece(
+           spec, ",", base::TRIM_WHITESPACE, base::SPLIT_WANT_NONEMPTY)) {
+    int port;
+    if (!base::StringToInt(item, &port) || port <= 0 ||
+        port > std::numeric_limits<uint16_t>::max()) {
+      return false;
+    }
+    ports->insert(static_cast<uint16_t>(port));
+  }
+  return true;
+}
+
+ReverseListener::Waiter::Waiter(std::unique_ptr<StreamSocket>* socket,
+                                CompletionOnceCallback callback)
+    : socket(socket), callback(std::move(callback)) {}
+
+ReverseListener::Waiter::Waiter(Waiter&& other) = default;
+
+ReverseListener::Waiter& ReverseListener::Waiter::operator=(Waiter&& other) =
+    default;
+
+ReverseListener::Waiter::~Waiter() = default;
+
+ReverseListener::Port::Port() = default;
+
+ReverseListener::Port::~Port() = default;
+
+ReverseListener::ReverseListener(const IPAddress& bind_address,
+                                 const std::set<uint16_t>& allowed_ports,
+                                 NetLog* net_log)
+    : bind_address_(bind_address),
+      allowed_ports_(allowed_ports),
+      net_log_(net_log) {}
+
+ReverseListener::~ReverseListener() = default;
+
+int ReverseListener::Accept(uint16_t port,
+                            std::unique_ptr<StreamSocket>* socket,
+                            CompletionOnceCallback callback) {
+  if (allowed_ports_.count(port) == 0) {
+    LOG(WARNING) << "Refused reverse tunnel for port " << port;
+    return ERR_ACCESS_DENIED;
+  }
+
+  auto& entry = ports_[port];
+  if (!entry) {
+    auto server_socket =
+        std::make_unique<TCPServerSocket>(net_log_, NetLogSource());
+    IPEndPoint endpoint(bind_address_, port);
+    int rv = server_socket->Listen(endpoint, kListenBackLog);
+    if (rv != OK) {
+      LOG(ERROR) << "Failed to listen on " << endpoint.ToString()
+                 << " for reverse tunnels: " << ErrorToShortString(rv);
+      ports_.erase(port);
+      return rv;
+    }
+    LOG(INFO) << "Listening on " << endpoint.ToString()
+              << " for reverse tunnels";
+    entry = std::make_unique<Port>();
+    entry->server_socket = std::move(server_socket);
+  }
+
+  if (entry->accepted_socket) {
+    *socket = std::move(entry->accepted_socket);
+    return OK;
+  }
+  entry->waiters.emplace_back(socket, std::move(callback));
+  DoAcceptLoop(port);
+  return ERR_IO_PENDING;
+}
+
+void ReverseListener::CancelAccept(std::unique_ptr<StreamSocket>* socket) {
+  for (auto& item : ports_) {
+    auto& waiters = item.second->waiters;
+    for (auto it = waiters.begin(); it != waiters.end(); ++it) {
+      if (it->socket == socket) {
+        waiters.erase(it);
+        return;
+      }
+    }
+  }
+}
+
+void ReverseListener::DoAcceptLoop(uint16_t port) {
+  Port* entry = ports_[port].get();
+  while (!entry->waiters.empty() && !entry->accept_pending &&
+         !entry->accepted_socket) {
+    int rv = entry->server_socket->Accept(
+        &entry->accepted_socket,
+        base::BindOnce(&ReverseListener::OnAcceptComplete,
+                       weak_ptr_factory_.GetWeakPtr(), port));
+    if (rv == ERR_IO_PENDING) {
+      entry->accept_pending = true;
+      return;
+    }
+    HandleAcceptResult(entry, rv);
+  }
+}
+
+void ReverseListener::OnAcceptComplete(uint16_t port, int result) {
+  Port* entry = ports_[port].get();
+  entry->accept_pending = false;
+  HandleAcceptResult(entry, result);
+  DoAcceptLoop(port);
+}
+
+void ReverseListener::HandleAcceptResult(Port* port, int result) {
+  if (result != OK) {
+    LOG(ERROR) << "Reverse accept error: rv=" << result;
+    port->accepted_socket.reset();
+  }
+  // Kept for the next tunnel if the waiting one went away.
+  if (port->waiters.empty())
+    return;
+
+  Waiter waiter = std::move(port->waiters.front());
+  port->waiters.pop_front();
+  if (result == OK)
+    *waiter.socket = std::move(port->accepted_socket);
+  std::move(waiter.callback).Run(result);
+}
+
+}  // namespace net
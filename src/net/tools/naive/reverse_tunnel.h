@@ -0,0 +1,108 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_REVERSE_TUNNEL_H_
+#define NET_TOOLS_NAIVE_REVERSE_TUNNEL_H_
+
+#include <cstdint>
+#include <deque>
+#include <map>
+#include <memory>
+#include <set>
+#include <string>
+#include <vector>
+
+#include "base/macros.h"
+#include "base/memory/weak_ptr.h"
+#include "base/strings/string_piece.h"
+#include "net/base/completion_once_callback.h"
+#include "net/base/host_port_pair.h"
+#include "net/base/ip_address.h"
+
+namespace net {
+
+class NetLog;
+class ServerSocket;
+class StreamSocket;
+
+// A client asks for a reverse tunnel with a CONNECT request to this host and
+// the remote port. The proxy server replies once it has accepted a
+// connection on that port, and the tunnel then carries that connection.
+constexpr char kReverseTunnelHost[] = "reverse.invalid";
+
+// Forwards connections to |remote_port| on the proxy server to |target|.
+struct ReverseForward {
+  uint16_t remote_port = 0;
+  HostPortPair target;
+
+  std::string ToString() const;
+};
+
+// Parses comma separated <remote port>:<host>:<port>.
+bool ParseReverseForwards(base::StringPiece spec,
+                          std::vector<ReverseForward>* forwards);
+
+// Parses comma separated ports.
+bool ParseReversePorts(base::StringPiece spec, std::set<uint16_t>* ports);
+
+// Listens on the ports asked for by reverse tunnels and hands each accepted
+// connection to a tunnel waiting on its port. A port is opened by the first
+// tunnel asking for it, and only accepts while tunnels are waiting.
+class ReverseListener {
+ public:
+  // Only ports in |allowed_ports| are opened.
+  ReverseListener(const IPAddress& bind_address,
+                  const std::set<uint16_t>& allowed_ports,
+                  NetLog* net_log);
+  ~ReverseListener();
+
+  // Waits for a connection to |port|. Returns OK if one is already accepted,
+  // ERR_ACCESS_DENIED if the port is not allowed, or ERR_IO_PENDING and calls
+  // |callback| later. |*socket| is set on success, and also identifies the
+  // wait for CancelAccept().
+  int Accept(uint16_t port,
+             std::unique_ptr<StreamSocket>* socket,
+             CompletionOnceCallback callback);
+
+  void CancelAccept(std::unique_ptr<StreamSocket>* socket);
+
+ private:
+  struct Waiter {
+    Waiter(std::unique_ptr<StreamSocket>* socket,
+           CompletionOnceCallback callback);
+    Waiter(Waiter&& other);
+    Waiter& operator=(Waiter&& other);
+    ~Waiter();
+
+    std::unique_ptr<StreamSocket>* socket;
+    CompletionOnceCallback callback;
+  };
+
+  struct Port {
+    Port();
+    ~Port();
+
+    std::unique_ptr<ServerSocket> server_socket;
+    // Accepted with no tunnel waiting, kept for the next one.
+    std::unique_ptr<StreamSocket> accepted_socket;
+    bool accept_pending = false;
+    std::deque<Waiter> waiters;
+  };
+
+  void DoAcceptLoop(uint16_t port);
+  void OnAcceptComplete(uint16_t port, int result);
+  void HandleAcceptResult(Port* port, int result);
+
+  IPAddress bind_address_;
+  std::set<uint16_t> allowed_ports_;
+  NetLog* net_log_;
+  std::map<uint16_t, std::unique_ptr<Port>> ports_;
+
+  base::WeakPtrFactory<ReverseListener> weak_ptr_factory_{this};
+
+  DISALLOW_COPY_AND_ASSIGN(ReverseListener);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_REVERSE_TUNNEL_H_
@@ -0,0 +1,585 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#include "net/tools/naive/soak_test.h"
+
+#include <cstring>
+#include <iostream>
+#include <map>
+#include <memory>
+#include <string>
+#include <utility>
+#include <vector>
+
+#include "base/bind.h"
+#include "base/callback.h"
+#include "base/location.h"
+#include "base/macros.h"
+#include "base/memory/weak_ptr.h"
+#include "base/process/process_metrics.h"
+#include "base/rand_util.h"
+#include "base/run_loop.h"
+#include "base/stl_util.h"
+#include "base/threading/thread_task_runner_handle.h"
+#include "base/time/time.h"
+#include "base/timer/timer.h"
+#include "build/build_config.h"
+#include "net/base/address_list.h"
+#include "net/base/io_buffer.h"
+#include "net/base/ip_address.h"
+#include "net/base/ip_endpoint.h"
+#include "net/base/net_errors.h"
+#include "net/log/net_log_source.h"
+#include "net/socket/stream_socket.h"
+#include "net/socket/tcp_client_socket.h"
+#include "net/socket/tcp_server_socket.h"
+#include "net/tools/naive/naive_protocol.h"
+#include "net/tools/naive/naive_proxy.h"
+
+#if defined(OS_POSIX)
+#include <sys/socket.h>
+#endif
+
+namespace net {
+namespace {
+constexpr int kListenBackLog = 4096;
+constexpr int kBufferSize = 32 * 1024;
+constexpr int kMaxPayloadSize = 256 * 1024;
+// Client, accepted by naive, connected by naive, and accepted by echo.
+constexpr unsigned int kFdsPerTunnel = 4;
+constexpr unsigned int kSpareFds = 256;
+constexpr int kMaxLatencyMs = 100;
+constexpr base::TimeDelta kDropTime = base::TimeDelta::FromSeconds(1);
+constexpr base::TimeDelta kTunnelTimeout = base::TimeDelta::FromSeconds(60);
+constexpr base::TimeDelta kCleanupTimeout = base::TimeDelta::FromSeconds(10);
+constexpr base::TimeDelta kPollInterval =
+    base::TimeDelta::FromMilliseconds(100);
+// Allows for allocator caches warmed up by the first round.
+constexpr size_t kMaxMemoryGrowth = 64 * 1024 * 1024;
+
+// Sent as the first payload byte to tell the echo server what to do.
+enum class Chaos : char {
+  kEcho = 'e',
+  // Echoes with random delays.
+  kLatency = 'l',
+  // Resets the connection after the first read.
+  kReset = 'r',
+  // Reads and discards everything.
+  kDrop = 'd',
+  // The client closes the tunnel right after writing.
+  kAbort = 'a',
+};
+
+constexpr Chaos kChaosModes[] = {Chaos::kEcho, Chaos::kLatency, Chaos::kReset,
+                                 Chaos::kDrop, Chaos::kAbort};
+
+// SOCKS5 greeting with no authentication, and CONNECT to an IPv4 address.
+constexpr char kSocksGreet[] = {0x05, 0x01, 0x00};
+constexpr char kSocksConnect[] = {0x05, 0x01, 0x00, 0x01};
+// Replies to both.
+constexpr char kSocksReply[] = {0x05, 0x00, 0x05, 0x00, 0x00, 0x01,
+                                0x00, 0x00, 0x00, 0x00, 0x00, 0x00};
+
+void ResetOnClose(StreamSocket* socket) {
+#if defined(OS_POSIX)
+  // Sockets accepted by TCPServerSocket are TCPClientSocket.
+  int fd = static_cast<TCPClientSocket*>(socket)->SocketDescriptorForTesting();
+  struct linger linger = {1, 0};
+  setsockopt(fd, SOL_SOCKET, SO_LINGER, &linger, sizeof(linger));
+#endif
+}
+
+class EchoSession {
+ public:
+  EchoSession(std::unique_ptr<StreamSocket> socket,
+              const NetworkTrafficAnnotationTag& traffic_annotation,
+              base::OnceClosure done_callback)
+      : socket_(std::move(socket)),
+        traffic_annotation_(traffic_annotation),
+        done_callback_(std::move(done_callback)),
+        chaos_known_(false),
+        chaos_(Chaos::kEcho) {}
+
+  void Start() { DoRead(); }
+
+ private:
+  void DoRead() {
+    while (true) {
+      read_buf_ = base::MakeRefCounted<IOBuffer>(kBufferSize);
+      int rv = socket_->Read(read_buf_.get(), kBufferSize,
+                             base::BindOnce(&EchoSession::OnReadComplete,
+                                            weak_ptr_factory_.GetWeakPtr()));
+      if (rv == ERR_IO_PENDING)
+        return;
+      if (!HandleRead(rv))
+        return;
+    }
+  }
+
+  void OnReadComplete(int result) {
+    if (HandleRead(result))
+      DoRead();
+  }
+
+  // Returns true to keep reading.
+  bool HandleRead(int result) {
+    if (result <= 0) {
+      Finish();
+      return false;
+    }
+    if (!chaos_known_) {
+      chaos_ = static_cast<Chaos>(read_buf_->data()[0]);
+      chaos_known_ = true;
+      if (chaos_ == Chaos::kReset) {
+        ResetOnClose(socket_.get());
+        Finish();
+        return false;
+      }
+    }
+    if (chaos_ == Chaos::kDrop)
+      return true;
+
+    write_buf_ = base::MakeRefCounted<DrainableIOBuffer>(std::move(read_buf_),
+                                                         result);
+    if (chaos_ == Chaos::kLatency) {
+      base::ThreadTaskRunnerHandle::Get()->PostDelayedTask(
+          FROM_HERE,
+          base::BindOnce(&EchoSession::DoWrite,
+                         weak_ptr_factory_.GetWeakPtr()),
+          base::TimeDelta::FromMilliseconds(base::RandInt(0, kMaxLatencyMs)));
+    } else {
+      DoWrite();
+    }
+    return false;
+  }
+
+  void DoWrite() {
+    while (write_buf_->BytesRemaining() > 0) {
+      int rv = socket_->Write(write_buf_.get(), write_buf_->BytesRemaining(),
+                              base::BindOnce(&EchoSession::OnWriteComplete,
+                                             weak_ptr_factory_.GetWeakPtr()),
+                              traffic_annotation_);
+      if (rv == ERR_IO_PENDING)
+        return;
+      if (rv < 0) {
+        Finish();
+        return;
+      }
+      write_buf_->DidConsume(rv);
+    }
+    write_buf_ = nullptr;
+    DoRead();
+  }
+
+  void OnWriteComplete(int result) {
+    if (result < 0) {
+      Finish();
+      return;
+    }
+    write_buf_->DidConsume(result);
+    DoWrite();
+  }
+
+  void Finish() {
+    weak_ptr_factory_.InvalidateWeakPtrs();
+    socket_->Disconnect();
+    std::move(done_callback_).Run();
+  }
+
+  std::unique_ptr<StreamSocket> socket_;
+  const NetworkTrafficAnnotationTag& traffic_annotation_;
+  base::OnceClosure done_callback_;
+  bool chaos_known_;
+  Chaos chaos_;
+  scoped_refptr<IOBuffer> read_buf_;
+  scoped_refptr<DrainableIOBuffer> write_buf_;
+
+  base::WeakPtrFactory<EchoSession> weak_ptr_factory_{this};
+
+  DISALLOW_COPY_AND_ASSIGN(EchoSession);
+};
+
+class EchoServer {
+ public:
+  EchoServer(NetLog* net_log,
+             const NetworkTrafficAnnotationTag& traffic_annotation)
+      : listen_socket_(net_log, NetLogSource()),
+        traffic_annotation_(traffic_annotation),
+        last_id_(0) {}
+
+  int Listen(IPEndPoint* address) {
+    int rv = listen_socket_.Listen(IPEndPoint(IPAddress::IPv4Localhost(), 0),
+                                   kListenBackLog);
+    if (rv != OK)
+      return rv;
+    rv = listen_socket_.GetLocalAddress(address);
+    if (rv != OK)
+      return rv;
+    DoAcceptLoop();
+    return OK;
+  }
+
+  size_t num_sessions() const { return session_by_id_.size(); }
+
+ private:
+  void DoAcceptLoop() {
+    int rv;
+    do {
+      rv = listen_socket_.Accept(
+          &accepted_socket_, base::BindOnce(&EchoServer::OnAcceptComplete,
+                                            weak_ptr_factory_.GetWeakPtr()));
+      if (rv == ERR_IO_PENDING)
+        return;
+      HandleAcceptResult(rv);
+    } while (rv == OK);
+  }
+
+  void OnAcceptComplete(int result) {
+    HandleAcceptResult(result);
+    if (result == OK)
+      DoAcceptLoop();
+  }
+
+  void HandleAcceptResult(int result) {
+    if (result != OK) {
+      std::cerr << "Echo accept error: " << ErrorToShortString(result)
+                << std::endl;
+      return;
+    }
+    unsigned int id = ++last_id_;
+    auto session = std::make_unique<EchoSession>(
+        std::move(accepted_socket_), traffic_annotation_,
+        base::BindOnce(&EchoServer::OnSessionDone,
+                       weak_ptr_factory_.GetWeakPtr(), id));
+    auto* session_ptr = session.get();
+    session_by_id_[id] = std::move(session);
+    session_ptr->Start();
+  }
+
+  void OnSessionDone(unsigned int id) {
+    auto it = session_by_id_.find(id);
+    if (it == session_by_id_.end())
+      return;
+    // Called from the session's own callback.
+    base::ThreadTaskRunnerHandle::Get()->DeleteSoon(FROM_HERE,
+                                                    std::move(it->second));
+    session_by_id_.erase(it);
+  }
+
+  TCPServerSocket listen_socket_;
+  const NetworkTrafficAnnotationTag& traffic_annotation_;
+  std::unique_ptr<StreamSocket> accepted_socket_;
+  unsigned int last_id_;
+  std::map<unsigned int, std::unique_ptr<EchoSession>> session_by_id_;
+
+  base::WeakPtrFactory<EchoServer> weak_ptr_factory_{this};
+
+  DISALLOW_COPY_AND_ASSIGN(EchoServer);
+};
+
+// Opens one tunnel through naive to the echo server, and checks what comes
+// back against its chaos mode.
+class SoakClient {
+ public:
+  // |done_callback| gets whether the tunnel behaved as expected.
+  SoakClient(const IPEndPoint& proxy_address,
+             const IPEndPoint& echo_address,
+             Chaos chaos,
+             int payload_size,
+             NetLog* net_log,
+             const NetworkTrafficAnnotationTag& traffic_annotation,
+             base::OnceCallback<void(bool)> done_callback)
+      : socket_(std::make_unique<TCPClientSocket>(AddressList(proxy_address),
+                                                  nullptr,
+                                                  nullptr,
+                                                  net_log,
+                                                  NetLogSource())),
+        chaos_(chaos),
+        traffic_annotation_(traffic_annotation),
+        done_callback_(std::move(done_callback)) {
+    std::string request(kSocksGreet, base::size(kSocksGreet));
+    request.append(kSocksConnect, base::size(kSocksConnect));
+    const auto& bytes = echo_address.address().bytes();
+    request.append(bytes.begin(), bytes.end());
+    request.push_back(echo_address.port() >> 8);
+    request.push_back(echo_address.port() & 0xff);
+
+    payload_ = base::RandBytesAsString(payload_size);
+    payload_[0] = static_cast<char>(chaos_);
+
+    expected_.assign(kSocksReply, base::size(kSocksReply));
+    if (chaos_ == Chaos::kEcho || chaos_ == Chaos::kLatency)
+      expected_.append(payload_);
+
+    // SOCKS requests are pipelined with the payload.
+    request.append(payload_);
+    auto buf = base::MakeRefCounted<StringIOBuffer>(std::move(request));
+    write_buf_ = base::MakeRefCounted<DrainableIOBuffer>(buf, buf->size());
+  }
+
+  void Start() {
+    timer_.Start(FROM_HERE, kTunnelTimeout,
+                 base::BindOnce(&SoakClient::Finish, base::Unretained(this),
+                                false, "timed out"));
+    int rv = socket_->Connect(base::BindOnce(&SoakClient::OnConnectComplete,
+                                             base::Unretained(this)));
+    if (rv != ERR_IO_PENDING)
+      OnConnectComplete(rv);
+  }
+
+ private:
+  void OnConnectComplete(int result) {
+    if (result != OK) {
+      Finish(false, "connect failed");
+      return;
+    }
+    // Reads while writing, or the echo would fill up the buffers.
+    DoRead();
+    if (done_callback_)
+      DoWrite();
+  }
+
+  void DoRead() {
+    while (done_callback_) {
+      read_buf_ = base::MakeRefCounted<IOBuffer>(kBufferSize);
+      int rv = socket_->Read(read_buf_.get(), kBufferSize,
+                             base::BindOnce(&SoakClient::OnReadComplete,
+                                            base::Unretained(this)));
+      if (rv == ERR_IO_PENDING)
+        return;
+      HandleRead(rv);
+    }
+  }
+
+  void OnReadComplete(int result) {
+    HandleRead(result);
+    DoRead();
+  }
+
+  void HandleRead(int result) {
+    if (result > 0) {
+      received_.append(read_buf_->data(), result);
+      if (received_.size() > expected_.size() ||
+          std::memcmp(received_.data(), expected_.data(), received_.size())) {
+        Finish(false, "unexpected data");
+      } else if (received_.size() == expected_.size() &&
+                 (chaos_ == Chaos::kEcho || chaos_ == Chaos::kLatency)) {
+        Finish(true, nullptr);
+      }
+      return;
+    }
+    // Closed or reset by the other end.
+    if (chaos_ == Chaos::kReset) {
+      Finish(true, nullptr);
+    } else {
+      Finish(false, "closed early");
+    }
+  }
+
+  void DoWrite() {
+    while (write_buf_->BytesRemaining() > 0) {
+      int rv = socket_->Write(write_buf_.get(), write_buf_->BytesRemaining(),
+                              base::BindOnce(&SoakClient::OnWriteComplete,
+                                             base::Unretained(this)),
+                              traffic_annotation_);
+      if (rv == ERR_IO_PENDING)
+        return;
+      if (!HandleWrite(rv))
+        return;
+    }
+    OnWriteDone();
+  }
+
+  void OnWriteComplete(int result) {
+    if (HandleWrite(result))
+      DoWrite();
+  }
+
+  // Returns true to keep writing.
+  bool HandleWrite(int result) {
+    if (!done_callback_)
+      return false;
+    if (result < 0) {
+      // The echo server may reset before all is written.
+      if (chaos_ == Chaos::kReset) {
+        Finish(true, nullptr);
+      } else {
+        Finish(false, "write failed");
+      }
+      return false;
+    }
+    write_buf_->DidConsume(result);
+    return true;
+  }
+
+  void OnWriteDone() {
+    if (chaos_ == Chaos::kAbort) {
+      Finish(true, nullptr);
+    } else if (chaos_ == Chaos::kDrop) {
+      timer_.Start(FROM_HERE, kDropTime,
+                   base::BindOnce(&SoakClient::Finish, base::Unretained(this),
+                                  true, nullptr));
+    }
+  }
+
+  void Finish(bool ok, const char* error) {
+    if (!done_callback_)
+      return;
+    if (!ok) {
+      std::cerr << "Tunnel " << static_cast<char>(chaos_) << " "
+                << payload_.size() << " bytes: " << error << std::endl;
+    }
+    timer_.Stop();
+    socket_->Disconnect();
+    std::move(done_callback_).Run(ok);
+  }
+
+  std::unique_ptr<TCPClientSocket> socket_;
+  Chaos chaos_;
+  const NetworkTrafficAnnotationTag& traffic_annotation_;
+  base::OnceCallback<void(bool)> done_callback_;
+  std::string payload_;
+  // The SOCKS replies and the echoed payload.
+  std::string expected_;
+  std::string received_;
+  scoped_refptr<IOBuffer> read_buf_;
+  scoped_refptr<DrainableIOBuffer> write_buf_;
+  base::OneShotTimer timer_;
+
+  DISALLOW_COPY_AND_ASSIGN(SoakClient);
+};
+
+void OnClientDone(int* pending,
+                  int* failures,
+                  base::RepeatingClosure quit_closure,
+                  bool ok) {
+  if (!ok)
+    ++*failures;
+  if (--*pending == 0)
+    quit_closure.Run();
+}
+
+void RunFor(base::TimeDelta delay) {
+  base::RunLoop run_loop;
+  base::ThreadTaskRunnerHandle::Get()->PostDelayedTask(
+      FROM_HERE, run_loop.QuitClosure(), delay);
+  run_loop.Run();
+}
+
+int GetOpenFdCount(const base::ProcessMetrics& metrics) {
+#if defined(OS_POSIX)
+  return metrics.GetOpenFdCount();
+#else
+  return 0;
+#endif
+}
+
+size_t GetMemoryUsage(const base::ProcessMetrics& metrics) {
+#if defined(OS_LINUX) || defined(OS_CHROMEOS) || defined(OS_ANDROID)
+  return metrics.GetResidentSetSize();
+#else
+  return 0;
+#endif
+}
+}  // namespace
+
+bool RunSoakTest(int tunnels,
+                 int rounds,
+                 HttpNetworkSession* session,
+                 NetLog* net_log,
+                 const NetworkTrafficAnnotationTag& traffic_annotation) {
+#if defined(OS_POSIX)
+  base::IncreaseFdLimitTo(tunnels * kFdsPerTunnel + kSpareFds);
+#endif
+
+  EchoServer echo_server(net_log, traffic_annotation);
+  IPEndPoint echo_address;
+  int rv = echo_server.Listen(&echo_address);
+  if (rv != OK) {
+    std::cerr << "Failed to listen: " << ErrorToShortString(rv) << std::endl;
+    return false;
+  }
+
+  auto listen_socket =
+      std::make_unique<TCPServerSocket>(net_log, NetLogSource());
+  rv = listen_socket->Listen(IPEndPoint(IPAddress::IPv4Localhost(), 0),
+                             kListenBackLog);
+  IPEndPoint proxy_address;
+  if (rv == OK)
+    rv = listen_socket->GetLocalAddress(&proxy_address);
+  if (rv != OK) {
+    std::cerr << "Failed to listen: " << ErrorToShortString(rv) << std::endl;
+    return false;
+  }
+  NaiveProxy naive_proxy(std::move(listen_socket), ClientProtocol::kSocks5,
+                         /*listen_user=*/"", /*listen_pass=*/"",
+                         /*concurrency=*/1, /*compression=*/false,
+                         /*buffer_memory=*/0, /*resolver=*/nullptr,
+                         /*nat64_detector=*/nullptr, session,
+                         traffic_annotation);
+
+  auto metrics = base::ProcessMetrics::CreateCurrentProcessMetrics();
+  // Lets naive start accepting before counting.
+  RunFor(kPollInterval);
+  const int baseline_fds = GetOpenFdCount(*metrics);
+  size_t baseline_memory = 0;
+
+  bool passed = true;
+  for (int round = 1; round <= rounds; ++round) {
+    base::TimeTicks start = base::TimeTicks::Now();
+    int pending = tunnels;
+    int failures = 0;
+    base::RunLoop run_loop;
+    std::vector<std::unique_ptr<SoakClient>> clients;
+    for (int i = 0; i < tunnels; ++i) {
+      Chaos chaos = kChaosModes[base::RandInt(
+          0, static_cast<int>(base::size(kChaosModes)) - 1)];
+      clients.push_back(std::make_unique<SoakClient>(
+          proxy_address, echo_address, chaos,
+          base::RandInt(1, kMaxPayloadSize), net_log, traffic_annotation,
+          base::BindOnce(&OnClientDone, &pending, &failures,
+                         run_loop.QuitClosure())));
+    }
+    for (auto& client : clients)
+      client->Start();
+    run_loop.Run();
+    clients.clear();
+    base::TimeDelta elapsed = base::TimeTicks::Now() - start;
+
+    // Waits for naive and the echo server to close their ends.
+    base::TimeTicks deadline = base::TimeTicks::Now() + kCleanupTimeout;
+    while ((naive_proxy.num_connections() > 0 ||
+            echo_server.num_sessions() > 0 ||
+            GetOpenFdCount(*metrics) > baseline_fds) &&
+           base::TimeTicks::Now() < deadline) {
+      RunFor(kPollInterval);
+    }
+    size_t connections = naive_proxy.num_connections();
+    int fds = GetOpenFdCount(*metrics);
+    size_t memory = GetMemoryUsage(*metrics);
+    if (round == 1)
+      baseline_memory = memory;
+
+    std::cout << "Round " << round << ": " << tunnels << " tunnels in "
+              << elapsed.InMilliseconds() << " ms, " << failures
+              << " failed, " << connections << " connections and "
+              << fds - baseline_fds << " fds left, " << memory / 1024
+              << " KiB resident" << std::endl;
+
+    if (failures > 0 || connections > 0 || fds > baseline_fds) {
+      passed = false;
+    }
+    if (memory > baseline_memory + kMaxMemoryGrowth) {
+      std::cerr << "Memory grew by " << (memory - baseline_memory) / 1024
+                << " KiB since round 1" << std::endl;
+      passed = false;
+    }
+    if (!passed)
+      break;
+  }
+
+  std::cout << (passed ? "PASSED" : "FAILED") << std::endl;
+  return passed;
+}
+
+}  // namespace net
@@ -0,0 +1,27 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+#ifndef NET_TOOLS_NAIVE_SOAK_TEST_H_
+#define NET_TOOLS_NAIVE_SOAK_TEST_H_
+
+namespace net {
+
+class HttpNetworkSession;
+class NetLog;
+struct NetworkTrafficAnnotationTag;
+
+// Runs a NaiveProxy listening with socks against a local echo server, with
+// |session| connecting directly. Each of the |rounds| opens |tunnels|
+// concurrent tunnels, each of which randomly has the echo server add latency,
+// reset, or black hole it, or aborts on the client side.
+//
+// Returns false if a tunnel gets wrong data or hangs, or if connections, file
+// descriptors or memory are not released after a round.
+bool RunSoakTest(int tunnels,
+                 int rounds,
+                 HttpNetworkSession* session,
+                 NetLog* net_log,
+                 const NetworkTrafficAnnotationTag& traffic_annotation);
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_SOAK_TEST_H_
@@ -0,0 +1,144 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/spare_session.h"
+
+#include <utility>
+
+#include "base/bind.h"
+#include "base/logging.h"
+#include "net/base/load_flags.h"
+#include "net/base/net_errors.h"
+#include "net/base/privacy_mode.h"
+#include "net/base/request_priority.h"
+#include "net/http/http_network_session.h"
+#include "net/socket/client_socket_handle.h"
+#include "net/socket/client_socket_pool_manager.h"
+#include "net/socket/next_proto.h"
+#include "net/socket/socket_tag.h"
+#include "net/socket/stream_socket.h"
+#include "net/spdy/spdy_session.h"
+#include "net/spdy/spdy_session_pool.h"
+
+namespace net {
+
+namespace {
+constexpr int kFreshnessCheckSeconds = 30;
+}  // namespace
+
+SpareSession::SpareSession(const ProxyServer& proxy_server,
+                           const SSLConfig& proxy_ssl_config,
+                           HttpNetworkSession* session,
+                           const NetLogWithSource& net_log)
+    : proxy_server_(proxy_server),
+      proxy_ssl_config_(proxy_ssl_config),
+      session_(session),
+      net_log_(net_log),
+      switchovers_(0),
+      refreshes_(0) {
+  DCHECK(proxy_server_.is_https());
+  direct_.UseDirect();
+  DoConnect();
+  freshness_timer_.Start(FROM_HERE,
+                         base::TimeDelta::FromSeconds(kFreshnessCheckSeconds),
+                         base::BindRepeating(&SpareSession::CheckFreshness,
+                                             weak_ptr_factory_.GetWeakPtr()));
+}
+
+SpareSession::~SpareSession() = default;
+
+bool SpareSession::HasSession(
+    const NetworkIsolationKey& network_isolation_key) {
+  base::WeakPtr<SpdySession> spdy_session =
+      session_->spdy_session_pool()->FindAvailableSession(
+          GetSessionKey(network_isolation_key),
+          /*enable_ip_based_pooling=*/false, /*is_websocket=*/false, net_log_);
+  return !!spdy_session;
+}
+
+bool SpareSession::Take(NetworkIsolationKey* network_isolation_key) {
+  if (!IsReady())
+    return false;
+
+  *network_isolation_key = network_isolation_key_;
+  ++switchovers_;
+  LOG(INFO) << "Switched to spare session ready for "
+            << (base::TimeTicks::Now() - ready_time_).InSeconds() << "s ("
+            << switchovers_ << " switchovers, " << refreshes_
+            << " refreshes)";
+  spdy_session_.reset();
+  ready_time_ = base::TimeTicks();
+  DoConnect();
+  return true;
+}
+
+SpdySessionKey SpareSession::GetSessionKey(
+    const NetworkIsolationKey& network_isolation_key) const {
+  // Same as the key of tunnels through the proxy server.
+  return SpdySessionKey(proxy_server_.host_port_pair(), ProxyServer::Direct(),
+                        PRIVACY_MODE_DISABLED,
+                        SpdySessionKey::IsProxySession::kTrue, SocketTag(),
+                        network_isolation_key, /*disable_secure_dns=*/true);
+}
+
+bool SpareSession::IsReady() const {
+  return spdy_session_ && spdy_session_->IsAvailable();
+}
+
+void SpareSession::DoConnect() {
+  DCHECK(!handle_);
+  network_isolation_key_ = NetworkIsolationKey::CreateTransient();
+  handle_ = std::make_unique<ClientSocketHandle>();
+  int result = InitSocketHandleForHttpRequest(
+      ClientSocketPoolManager::SSL_GROUP, proxy_server_.host_port_pair(),
+      LOAD_NORMAL, LOWEST, session_, direct_, proxy_ssl_config_,
+      proxy_ssl_config_, PRIVACY_MODE_DISABLED, network_isolation_key_,
+      /*disable_secure_dns=*/true, SocketTag(), net_log_, handle_.get(),
+      base::BindOnce(&SpareSession::OnConnectComplete,
+                     weak_ptr_factory_.GetWeakPtr()),
+      ClientSocketPool::ProxyAuthCallback());
+  if (result != ERR_IO_PENDING)
+    OnConnectComplete(result);
+}
+
+void SpareSession::OnConnectComplete(int result) {
+  std::unique_ptr<ClientSocketHandle> handle = std::move(handle_);
+  if (result != OK) {
+    // Retried on the next freshness check.
+    LOG(WARNING) << "Spare session failed to connect: "
+                 << ErrorToShortString(result);
+    return;
+  }
+  if (handle->socket()->GetNegotiatedProtocol() != kProtoHTTP2) {
+    LOG(WARNING) << "Spare session disabled: proxy server is not HTTP/2";
+    freshness_timer_.Stop();
+    return;
+  }
+  SpdySessionPool* pool = session_->spdy_session_pool();
+  result = pool->CreateAvailableSessionFromSocketHandle(
+      GetSessionKey(network_isolation_key_), proxy_server_.is_trusted_proxy(),
+      std::move(handle), net_log_, &spdy_session_);
+  if (result != OK) {
+    LOG(WARNING) << "Spare session failed: " << ErrorToShortString(result);
+    return;
+  }
+  ready_time_ = base::TimeTicks::Now();
+  LOG(INFO) << "Spare session ready";
+}
+
+void SpareSession::CheckFreshness() {
+  if (handle_ || IsReady())
+    return;
+
+  if (!ready_time_.is_null()) {
+    ++refreshes_;
+    LOG(INFO) << "Spare session went away after "
+              << (base::TimeTicks::Now() - ready_time_).InSeconds()
+              << "s, reconnecting (" << refreshes_ << " refreshes)";
+    ready_time_ = base::TimeTicks();
+  }
+  DoConnect();
+}
+
+}  // namespace net
@@ -0,0 +1,80 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_SPARE_SESSION_H_
+#define NET_TOOLS_NAIVE_SPARE_SESSION_H_
+
+#include <memory>
+
+#include "base/macros.h"
+#include "base/memory/weak_ptr.h"
+#include "base/time/time.h"
+#include "base/timer/timer.h"
+#include "net/base/network_isolation_key.h"
+#include "net/base/proxy_server.h"
+#include "net/log/net_log_with_source.h"
+#include "net/proxy_resolution/proxy_info.h"
+#include "net/spdy/spdy_session_key.h"
+#include "net/ssl/ssl_config.h"
+
+namespace net {
+
+class ClientSocketHandle;
+class HttpNetworkSession;
+class SpdySession;
+
+// Keeps an idle HTTP/2 session to an HTTPS proxy under its own network
+// isolation key. When the session of a tunnel slot is gone, the slot takes
+// the spare key and its next tunnel skips the TCP and TLS handshakes.
+class SpareSession {
+ public:
+  SpareSession(const ProxyServer& proxy_server,
+               const SSLConfig& proxy_ssl_config,
+               HttpNetworkSession* session,
+               const NetLogWithSource& net_log);
+  ~SpareSession();
+
+  // Whether tunnels with |network_isolation_key| have a session to reuse.
+  bool HasSession(const NetworkIsolationKey& network_isolation_key);
+
+  // Sets |*network_isolation_key| to the key of the spare session and starts
+  // a new spare. Returns false if no spare session is ready.
+  bool Take(NetworkIsolationKey* network_isolation_key);
+
+ private:
+  SpdySessionKey GetSessionKey(
+      const NetworkIsolationKey& network_isolation_key) const;
+  bool IsReady() const;
+
+  void DoConnect();
+  void OnConnectComplete(int result);
+  void CheckFreshness();
+
+  ProxyServer proxy_server_;
+  SSLConfig proxy_ssl_config_;
+  HttpNetworkSession* session_;
+  NetLogWithSource net_log_;
+  // Connects to the proxy server itself.
+  ProxyInfo direct_;
+
+  NetworkIsolationKey network_isolation_key_;
+  // Non-null while connecting.
+  std::unique_ptr<ClientSocketHandle> handle_;
+  base::WeakPtr<SpdySession> spdy_session_;
+  base::TimeTicks ready_time_;
+
+  // Spare sessions handed to tunnels.
+  int switchovers_;
+  // Spare sessions that went away unused and were replaced.
+  int refreshes_;
+
+  base::RepeatingTimer freshness_timer_;
+
+  base::WeakPtrFactory<SpareSession> weak_ptr_factory_{this};
+
+  DISALLOW_COPY_AND_ASSIGN(SpareSession);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_SPARE_SESSION_H_
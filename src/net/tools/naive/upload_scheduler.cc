@@ -0,0 +1,90 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#include "net/tools/naive/upload_scheduler.h"
+
+#include <utility>
+
+#include "base/check_op.h"
+#include "base/location.h"
+#include "base/threading/thread_task_runner_handle.h"
+
+namespace net {
+
+namespace {
+// Enough to keep the session's socket busy between two grants.
+constexpr int kMaxBytesInFlight = 256 * 1024;
+// Bytes credited to a waiting tunnel per round.
+constexpr int kQuantum = 16 * 1024;
+}  // namespace
+
+UploadScheduler::Request::Request(unsigned int id,
+                                  int size,
+                                  base::OnceClosure callback)
+    : id(id), size(size), deficit(0), callback(std::move(callback)) {}
+
+UploadScheduler::Request::Request(Request&& other) = default;
+
+UploadScheduler::Request& UploadScheduler::Request::operator=(
+    Request&& other) = default;
+
+UploadScheduler::Request::~Request() = default;
+
+UploadScheduler::UploadScheduler() : bytes_in_flight_(0) {}
+
+UploadScheduler::~UploadScheduler() = default;
+
+bool UploadScheduler::RequestWrite(unsigned int id,
+                                   int size,
+                                   base::OnceClosure callback) {
+  DCHECK_GT(size, 0);
+  if (active_requests_.empty() && bytes_in_flight_ < kMaxBytesInFlight) {
+    bytes_in_flight_ += size;
+    return true;
+  }
+  active_requests_.emplace_back(id, size, std::move(callback));
+  Schedule();
+  return false;
+}
+
+void UploadScheduler::OnWriteComplete(int size) {
+  bytes_in_flight_ -= size;
+  DCHECK_GE(bytes_in_flight_, 0);
+  Schedule();
+}
+
+bool UploadScheduler::CancelRequest(unsigned int id) {
+  for (auto it = active_requests_.begin(); it != active_requests_.end();
+       ++it) {
+    if (it->id == id) {
+      active_requests_.erase(it);
+      return true;
+    }
+  }
+  return false;
+}
+
+void UploadScheduler::Schedule() {
+  // Terminates because every round adds to the deficits.
+  while (!active_requests_.empty() && bytes_in_flight_ < kMaxBytesInFlight) {
+    Request request = std::move(active_requests_.front());
+    active_requests_.pop_front();
+    if (request.deficit < request.size) {
+      request.deficit += kQuantum;
+      active_requests_.push_back(std::move(request));
+      continue;
+    }
+    Grant(std::move(request));
+  }
+}
+
+void UploadScheduler::Grant(Request request) {
+  bytes_in_flight_ += request.size;
+  // Posted so the tunnel doesn't write from within another tunnel's
+  // completion callback.
+  base::ThreadTaskRunnerHandle::Get()->PostTask(FROM_HERE,
+                                                std::move(request.callback));
+}
+
+}  // namespace net
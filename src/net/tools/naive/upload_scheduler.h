@@ -0,0 +1,61 @@
+// Copyright 2021 klzgrad <kizdiv@gmail.com>. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+#ifndef NET_TOOLS_NAIVE_UPLOAD_SCHEDULER_H_
+#define NET_TOOLS_NAIVE_UPLOAD_SCHEDULER_H_
+
+#include <deque>
+
+#include "base/callback.h"
+#include "base/macros.h"
+
+namespace net {
+
+// Shares the upload path of one upstream session among its tunnels with
+// deficit round robin, so a bulk uploader cannot starve the others. Writes
+// to the server must be granted first, and only a bounded number of granted
+// bytes may be in flight. Tunnels return the grants of writes blocked by
+// stream flow control early, so those don't stall the other tunnels.
+class UploadScheduler {
+ public:
+  UploadScheduler();
+  ~UploadScheduler();
+
+  // Returns true if tunnel |id| may write |size| bytes right away. Otherwise
+  // returns false and posts |callback| once the write is granted. A tunnel
+  // has at most one request at a time.
+  bool RequestWrite(unsigned int id, int size, base::OnceClosure callback);
+
+  // Returns the |size| bytes of a granted write after it completes or is
+  // found blocked.
+  void OnWriteComplete(int size);
+
+  // Drops the request of tunnel |id|. Returns false if there was none.
+  bool CancelRequest(unsigned int id);
+
+ private:
+  struct Request {
+    Request(unsigned int id, int size, base::OnceClosure callback);
+    Request(Request&& other);
+    Request& operator=(Request&& other);
+    ~Request();
+
+    unsigned int id;
+    int size;
+    int deficit;
+    base::OnceClosure callback;
+  };
+
+  void Schedule();
+  void Grant(Request request);
+
+  // Round robin order of tunnels waiting for a grant.
+  std::deque<Request> active_requests_;
+  int bytes_in_flight_;
+
+  DISALLOW_COPY_AND_ASSIGN(UploadScheduler);
+};
+
+}  // namespace net
+#endif  // NET_TOOLS_NAIVE_UPLOAD_SCHEDULER_H_
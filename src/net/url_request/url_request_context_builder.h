@@ -312,6 +312,12 @@ class NET_EXPORT URLRequestContextBuilder {
       CreateHttpTransactionFactoryCallback
           create_http_network_transaction_factory);
 
+  // Sets a ClientSocketFactory to create all sockets of the context, e.g. to
+  // configure them. It must be destroyed after the created URLRequestContext.
+  void set_client_socket_factory(ClientSocketFactory* client_socket_factory) {
+    client_socket_factory_ = client_socket_factory;
+  }
+
   // Sets a ClientSocketFactory so a test can mock out sockets. The
   // ClientSocketFactory must be destroyed after the creates URLRequestContext.
   void set_client_socket_factory_for_testing(
@@ -386,6 +392,7 @@ class NET_EXPORT URLRequestContextBuilder {
   std::map<std::string, std::unique_ptr<URLRequestJobFactory::ProtocolHandler>>
       protocol_handlers_;
 
+  ClientSocketFactory* client_socket_factory_ = nullptr;
   ClientSocketFactory* client_socket_factory_for_testing_ = nullptr;
 
   DISALLOW_COPY_AND_ASSIGN(URLRequestContextBuilder);